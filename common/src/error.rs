@@ -0,0 +1,42 @@
+use ethereum_types::H256;
+use thiserror::Error;
+
+/// Failures of fetching and sampling that callers may want to tell apart. They travel inside
+/// `anyhow::Error` and can be recovered with `downcast_ref`.
+#[derive(Debug, Error)]
+pub enum SampleError {
+    #[error("batch not found")]
+    BatchNotFound,
+    #[error("invalid blob index {index:?}, batch has {blobs:?} blobs")]
+    InvalidBlobIndex { index: u32, blobs: usize },
+    #[error("invalid dimensions {rows:?}x{cols:?}")]
+    InvalidDimensions { rows: u32, cols: u32 },
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("segment with index {segment_index:?} unavailable, data root: {data_root:?}")]
+    SegmentUnavailable {
+        segment_index: usize,
+        data_root: H256,
+    },
+    #[error("file not finalized by any storage node yet, data root: {data_root:?}")]
+    FileNotFinalized { data_root: H256 },
+    #[error("not enough rows to recover the blob, available {available:?} of {rows:?}")]
+    NotRecoverable { available: usize, rows: usize },
+    #[error("invalid proof: {0}")]
+    ProofInvalid(String),
+    #[error("upstream timeout: {0}")]
+    UpstreamTimeout(String),
+    #[error("upstream unavailable: {0}")]
+    UpstreamUnavailable(String),
+    #[error("malformed segment: {0}")]
+    MalformedSegment(String),
+}
+
+impl From<jsonrpsee::core::Error> for SampleError {
+    fn from(e: jsonrpsee::core::Error) -> Self {
+        match e {
+            jsonrpsee::core::Error::RequestTimeout => Self::UpstreamTimeout(e.to_string()),
+            e => Self::UpstreamUnavailable(e.to_string()),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use types::{BlobDisperseInfo, BlobLocation};
 
+pub mod error;
+pub mod merkle;
 pub mod types;
 
 pub const ENTRY_SIZE: u32 = 256;
@@ -7,6 +9,10 @@ pub const ENTRY_PER_SEGMENT: u32 = 1024;
 pub const SEGMENT_SIZE: u32 = ENTRY_SIZE * ENTRY_PER_SEGMENT;
 pub const COEFF_SIZE: u32 = 32;
 pub const COMMITMENT_SIZE: u32 = 48;
+// blob bytes carried by one coefficient, the last byte is zero padding
+pub const COEFF_DATA_SIZE: u32 = COEFF_SIZE - 1;
+// rows are extended column-wise, original row `i` is stored as row `i * EXTENSION_FACTOR`
+pub const EXTENSION_FACTOR: u32 = 2;
 
 pub fn allocate_rows(blob_disperse_infos: &Vec<BlobDisperseInfo>) -> Vec<BlobLocation> {
     let n = blob_disperse_infos.len();
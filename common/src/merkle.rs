@@ -0,0 +1,96 @@
+use ethereum_types::H256;
+use tiny_keccak::{Hasher, Keccak};
+
+pub fn keccak256(data: &[&[u8]]) -> H256 {
+    let mut hasher = Keccak::v256();
+    for x in data {
+        hasher.update(x);
+    }
+    let mut output = [0u8; 32];
+    hasher.finalize(&mut output);
+    H256(output)
+}
+
+/// Root of a binary keccak Merkle tree over `leaves`, a node without a sibling is promoted to
+/// the next level unchanged. The root of an empty tree is zero.
+pub fn merkle_root(leaves: &[H256]) -> H256 {
+    if leaves.is_empty() {
+        return H256::zero();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => keccak256(&[left.as_bytes(), right.as_bytes()]),
+                [node] => *node,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Batch root committing to the row commitments of all blobs in the batch, leaves are the
+/// hashes of the commitments in blob order, then row order. The disperser does not publish row
+/// commitments yet, this is the layout the light node expects until it does.
+pub fn commitment_root<'a>(commitments: impl Iterator<Item = &'a [u8]>) -> H256 {
+    merkle_root(&commitments.map(|x| keccak256(&[x])).collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(i: u8) -> H256 {
+        H256::repeat_byte(i)
+    }
+
+    fn hash(left: H256, right: H256) -> H256 {
+        keccak256(&[left.as_bytes(), right.as_bytes()])
+    }
+
+    #[test]
+    fn merkle_root_of_small_trees() {
+        assert_eq!(merkle_root(&[]), H256::zero());
+        assert_eq!(merkle_root(&[leaf(1)]), leaf(1));
+        assert_eq!(merkle_root(&[leaf(1), leaf(2)]), hash(leaf(1), leaf(2)));
+        assert_eq!(
+            merkle_root(&[leaf(1), leaf(2), leaf(3), leaf(4)]),
+            hash(hash(leaf(1), leaf(2)), hash(leaf(3), leaf(4)))
+        );
+    }
+
+    #[test]
+    fn merkle_root_promotes_odd_nodes() {
+        assert_eq!(
+            merkle_root(&[leaf(1), leaf(2), leaf(3)]),
+            hash(hash(leaf(1), leaf(2)), leaf(3))
+        );
+        assert_eq!(
+            merkle_root(&[leaf(1), leaf(2), leaf(3), leaf(4), leaf(5)]),
+            hash(
+                hash(hash(leaf(1), leaf(2)), hash(leaf(3), leaf(4))),
+                leaf(5)
+            )
+        );
+    }
+
+    #[test]
+    fn commitment_root_hashes_commitments() {
+        let commitments = [vec![1u8; 48], vec![2u8; 48], vec![3u8; 48]];
+        let leaves: Vec<H256> = commitments
+            .iter()
+            .map(|x| keccak256(&[x.as_slice()]))
+            .collect();
+        assert_eq!(
+            commitment_root(commitments.iter().map(|x| x.as_slice())),
+            merkle_root(&leaves)
+        );
+        assert_ne!(
+            commitment_root(commitments.iter().rev().map(|x| x.as_slice())),
+            merkle_root(&leaves)
+        );
+        assert_eq!(commitment_root(std::iter::empty()), H256::zero());
+    }
+}
@@ -12,6 +12,10 @@ pub struct BlobDisperseInfo {
     pub blob_length: u64,
     pub rows: u32,
     pub cols: u32,
+    /// commitments of the rows of the extended matrix, anchored by `BatchHeader::batch_root`,
+    /// empty if the disperser did not list them
+    #[serde(default)]
+    pub commitments: Vec<Vec<u8>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,137 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ethereum_types::H256;
+use lru::LruCache;
+use tokio::task::JoinSet;
+use zgs_rpc::ZgsRPCClient;
+
+use crate::zgs_fetcher::ZgsNode;
+
+// data roots whose location is remembered
+const LOCATION_CACHE_ENTRIES: usize = 1024;
+// how long a location is trusted, nodes keep syncing files so it goes stale
+const LOCATION_TTL: Duration = Duration::from_secs(30);
+
+/// Which storage nodes hold the file of a data root
+#[derive(Clone, Debug, Default)]
+pub struct FileLocation {
+    /// nodes asked for the file info
+    pub asked: Vec<usize>,
+    /// nodes holding the finalized file
+    pub holders: Vec<usize>,
+    /// nodes that know the file but have not finalized it yet
+    pub pending: Vec<usize>,
+    /// nodes whose file info could not be fetched
+    pub unreachable: Vec<usize>,
+    pub tx_seq: Option<u64>,
+    /// position of the first entry of the file in the flow
+    pub start_entry_index: Option<u64>,
+}
+
+impl FileLocation {
+    /// The file is being stored but no node can serve it yet, missing segments are not withheld
+    pub fn not_finalized(&self) -> bool {
+        self.holders.is_empty() && !self.pending.is_empty()
+    }
+}
+
+/// Looks up the file info of data roots on storage nodes, remembering the answers for a while
+pub struct FileLocator {
+    locations: Mutex<LruCache<H256, (Instant, Arc<FileLocation>)>>,
+}
+
+impl FileLocator {
+    pub fn new() -> Self {
+        Self {
+            locations: Mutex::new(LruCache::new(
+                NonZeroUsize::new(LOCATION_CACHE_ENTRIES).expect("non zero capacity"),
+            )),
+        }
+    }
+
+    /// The location of the data root if it was looked up recently
+    pub fn cached(&self, data_root: H256) -> Option<Arc<FileLocation>> {
+        self.locations
+            .lock()
+            .expect("lock poisoned")
+            .get(&data_root)
+            .filter(|(at, _)| at.elapsed() < LOCATION_TTL)
+            .map(|(_, location)| location.clone())
+    }
+
+    /// Asks the nodes of indexes `asked` for the file info of the data root. The lookups run
+    /// in a `JoinSet`, so they stop if this future is dropped.
+    pub async fn locate(
+        &self,
+        nodes: &[ZgsNode],
+        asked: Vec<usize>,
+        data_root: H256,
+    ) -> Arc<FileLocation> {
+        if let Some(location) = self.cached(data_root) {
+            return location;
+        }
+
+        let timer = Instant::now();
+        let mut lookups = JoinSet::new();
+        for i in asked.iter().copied() {
+            let client = nodes[i].client.clone();
+            lookups.spawn(async move { (i, client.get_file_info(data_root).await) });
+        }
+        let mut location = FileLocation {
+            asked,
+            ..Default::default()
+        };
+        while let Some(joined) = lookups.join_next().await {
+            let (i, info) = match joined {
+                Ok(joined) => joined,
+                Err(e) => {
+                    error!("get file info task failed: {:?}", e);
+                    continue;
+                }
+            };
+            match info {
+                Ok(Some(info)) => {
+                    location.tx_seq = Some(info.tx.seq);
+                    location.start_entry_index = Some(info.tx.start_entry_index);
+                    if info.finalized {
+                        location.holders.push(i);
+                    } else {
+                        location.pending.push(i);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    debug!("Get file info from {:?} failed: {:?}", nodes[i].url, e);
+                    location.unreachable.push(i);
+                }
+            }
+        }
+        // answers arrive in any order, nodes are kept in configuration order
+        location.holders.sort_unstable();
+        location.pending.sort_unstable();
+        location.unreachable.sort_unstable();
+        info!(
+            "locate file used {:?}ms, data root: {:x?}, {:?}",
+            timer.elapsed().as_millis(),
+            data_root,
+            location
+        );
+
+        let location = Arc::new(location);
+        self.locations
+            .lock()
+            .expect("lock poisoned")
+            .put(data_root, (Instant::now(), location.clone()));
+        location
+    }
+}
+
+impl Default for FileLocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
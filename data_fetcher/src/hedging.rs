@@ -0,0 +1,111 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+// latest successful requests the hedge delay is learned from
+const LATENCY_WINDOW: usize = 256;
+// latencies needed before the learned delay replaces the default one
+const MIN_LATENCY_SAMPLES: usize = 16;
+// percentile of the latency after which a second node is asked
+const HEDGE_PERCENTILE: f64 = 0.95;
+// delay used until enough latencies are learned
+const DEFAULT_HEDGE_DELAY: Duration = Duration::from_millis(500);
+// lower bound of the delay, so a fast window does not hedge every request
+const MIN_HEDGE_DELAY: Duration = Duration::from_millis(20);
+// hedge tokens earned per request, a hedge costs one token so at most a tenth of the
+// requests are hedged
+const TOKENS_PER_REQUEST: f64 = 0.1;
+// tokens saved up while requests are fast, bounding bursts of hedges
+const MAX_TOKENS: f64 = 10.0;
+
+/// How often segment requests were hedged
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HedgeStats {
+    pub requests: u64,
+    pub hedged: u64,
+    /// hedges whose node answered first with a valid segment
+    pub hedge_wins: u64,
+    /// hedges skipped because the budget was used up
+    pub budget_exhausted: u64,
+    pub delay: Duration,
+}
+
+struct State {
+    latencies: VecDeque<Duration>,
+    delay: Duration,
+    tokens: f64,
+    stats: HedgeStats,
+}
+
+/// Learns when a segment request is late and bounds the extra requests sent for late ones
+pub struct Hedging {
+    state: Mutex<State>,
+}
+
+impl Hedging {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                latencies: VecDeque::with_capacity(LATENCY_WINDOW),
+                delay: DEFAULT_HEDGE_DELAY,
+                tokens: 0.0,
+                stats: HedgeStats::default(),
+            }),
+        }
+    }
+
+    /// How long to wait for a node before asking another one
+    pub fn delay(&self) -> Duration {
+        self.state.lock().expect("lock poisoned").delay
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.latencies.len() == LATENCY_WINDOW {
+            state.latencies.pop_front();
+        }
+        state.latencies.push_back(latency);
+        if state.latencies.len() >= MIN_LATENCY_SAMPLES {
+            let mut latencies: Vec<Duration> = state.latencies.iter().copied().collect();
+            latencies.sort();
+            let index = ((latencies.len() - 1) as f64 * HEDGE_PERCENTILE).round() as usize;
+            state.delay = latencies[index].max(MIN_HEDGE_DELAY);
+        }
+    }
+
+    /// Counts a segment request, earning a share of a hedge
+    pub fn record_request(&self) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.stats.requests += 1;
+        state.tokens = (state.tokens + TOKENS_PER_REQUEST).min(MAX_TOKENS);
+    }
+
+    /// Whether a hedge may be sent, spending its token
+    pub fn try_hedge(&self) -> bool {
+        let mut state = self.state.lock().expect("lock poisoned");
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            state.stats.hedged += 1;
+            true
+        } else {
+            state.stats.budget_exhausted += 1;
+            false
+        }
+    }
+
+    pub fn record_win(&self) {
+        self.state.lock().expect("lock poisoned").stats.hedge_wins += 1;
+    }
+
+    pub fn stats(&self) -> HedgeStats {
+        let state = self.state.lock().expect("lock poisoned");
+        HedgeStats {
+            delay: state.delay,
+            ..state.stats
+        }
+    }
+}
+
+impl Default for Hedging {
+    fn default() -> Self {
+        Self::new()
+    }
+}
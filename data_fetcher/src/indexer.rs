@@ -0,0 +1,172 @@
+use anyhow::{anyhow, Result};
+use common::error::SampleError;
+use ethereum_types::H256;
+use jsonrpsee::{core::RpcResult, http_client::HttpClient, proc_macros::rpc};
+use kv_rpc::build_client;
+use serde::{Deserialize, Serialize};
+
+use crate::shard::Shard;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShardConfig {
+    pub num_shard: usize,
+    pub shard_id: usize,
+}
+
+/// Storage node known to the indexer, with the shard of the flow it stores
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShardedNode {
+    pub url: String,
+    pub config: ShardConfig,
+}
+
+impl ShardedNode {
+    pub fn shard(&self) -> Shard {
+        Shard {
+            id: self.config.shard_id,
+            count: self.config.num_shard,
+        }
+    }
+}
+
+#[rpc(client, namespace = "indexer")]
+pub trait IndexerRpc {
+    #[method(name = "getFileLocations")]
+    async fn get_file_locations(&self, root: H256) -> RpcResult<Option<Vec<ShardedNode>>>;
+}
+
+/// Client of a storage indexer, which tells the storage nodes holding a file
+pub struct IndexerClient {
+    url: String,
+    client: HttpClient,
+}
+
+impl IndexerClient {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            client: build_client(url).map_err(|e| anyhow!(e.to_string()))?,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Storage nodes holding the file of the data root, empty if the indexer does not know it
+    pub async fn file_locations(&self, data_root: H256) -> Result<Vec<ShardedNode>> {
+        Ok(self
+            .client
+            .get_file_locations(data_root)
+            .await
+            .map_err(SampleError::from)?
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use jsonrpsee::{
+        http_server::{HttpServerBuilder, HttpServerHandle},
+        RpcModule,
+    };
+
+    use super::*;
+    use crate::zgs_fetcher::ZgsFetcher;
+
+    const STATIC_URL: &str = "http://127.0.0.1:1";
+    const INDEXED_URL: &str = "http://127.0.0.1:2";
+
+    /// Indexer knowing the static node and one more node for `known_root`
+    async fn stub_indexer(known_root: H256) -> (SocketAddr, HttpServerHandle) {
+        let server = HttpServerBuilder::default()
+            .build("127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut module = RpcModule::new(());
+        module
+            .register_method("indexer_getFileLocations", move |params, _| {
+                let root: H256 = params.one()?;
+                if root != known_root {
+                    return Ok(None);
+                }
+                Ok(Some(vec![
+                    ShardedNode {
+                        url: STATIC_URL.to_string(),
+                        config: ShardConfig {
+                            num_shard: 1,
+                            shard_id: 0,
+                        },
+                    },
+                    ShardedNode {
+                        url: INDEXED_URL.to_string(),
+                        config: ShardConfig {
+                            num_shard: 2,
+                            shard_id: 1,
+                        },
+                    },
+                ]))
+            })
+            .unwrap();
+        (addr, server.start(module).unwrap())
+    }
+
+    #[tokio::test]
+    async fn file_locations_from_indexer() {
+        let root = H256::repeat_byte(1);
+        let (addr, _handle) = stub_indexer(root).await;
+        let indexer = IndexerClient::new(&format!("http://{}", addr)).unwrap();
+
+        let nodes = indexer.file_locations(root).await.unwrap();
+        assert_eq!(
+            nodes.iter().map(|x| x.url.as_str()).collect::<Vec<_>>(),
+            [STATIC_URL, INDEXED_URL]
+        );
+        assert_eq!(nodes[1].shard(), Shard { id: 1, count: 2 });
+
+        let unknown = indexer.file_locations(H256::repeat_byte(2)).await.unwrap();
+        assert!(unknown.is_empty());
+    }
+
+    #[tokio::test]
+    async fn discovered_nodes_merge_with_static_list() {
+        let root = H256::repeat_byte(1);
+        let (addr, _handle) = stub_indexer(root).await;
+        let fetcher = ZgsFetcher::new(
+            &[STATIC_URL.to_string()],
+            0,
+            Some(&format!("http://{}", addr)),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(fetcher.discover(root).await.unwrap(), [0, 1]);
+        // nodes already known are not added again
+        assert_eq!(fetcher.discover(root).await.unwrap(), [0, 1]);
+
+        let nodes = fetcher.node_stats();
+        assert_eq!(
+            nodes.iter().map(|x| x.url.as_str()).collect::<Vec<_>>(),
+            [STATIC_URL, INDEXED_URL]
+        );
+        assert_eq!(nodes[1].shard, Some(Shard { id: 1, count: 2 }));
+    }
+
+    #[tokio::test]
+    async fn unreachable_indexer_is_an_error() {
+        let fetcher = ZgsFetcher::new(
+            &[STATIC_URL.to_string()],
+            0,
+            Some("http://127.0.0.1:1"),
+            None,
+        )
+        .unwrap();
+
+        assert!(fetcher.discover(H256::repeat_byte(1)).await.is_err());
+        assert_eq!(fetcher.node_stats().len(), 1);
+    }
+}
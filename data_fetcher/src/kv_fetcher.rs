@@ -1,5 +1,5 @@
 use anyhow::Result;
-use common::types::KVBatchInfo;
+use common::{error::SampleError, types::KVBatchInfo};
 use ethereum_types::H256;
 use jsonrpsee::http_client::HttpClient;
 use kv_rpc::KeyValueRpcClient;
@@ -22,7 +22,8 @@ pub async fn fetch_kv_batch_info(
                 MAX_QUERY_SIZE,
                 None,
             )
-            .await?
+            .await
+            .map_err(SampleError::from)?
         {
             raw_value.extend(result.data);
             if raw_value.len() as u64 == result.size {
@@ -34,3 +35,35 @@ pub async fn fetch_kv_batch_info(
     }
     Ok(serde_json::from_slice(&raw_value)?)
 }
+
+/// Up to `limit` keys of a stream following `after` in key order, from the first key if
+/// `after` is `None`, with their versions. Keys are listed with `get_first` and `get_next`
+/// without fetching their values.
+pub async fn fetch_kv_keys(
+    client: HttpClient,
+    stream_id: H256,
+    after: Option<Vec<u8>>,
+    limit: usize,
+) -> Result<Vec<(Vec<u8>, u64)>> {
+    let mut keys = vec![];
+    let mut next = match after {
+        Some(key) => {
+            client
+                .get_next(stream_id, Segment(key), 0, 0, false, None)
+                .await
+        }
+        None => client.get_first(stream_id, 0, 0, None).await,
+    }
+    .map_err(SampleError::from)?;
+    while let Some(pair) = next {
+        keys.push((pair.key.clone(), pair.version));
+        if keys.len() >= limit {
+            break;
+        }
+        next = client
+            .get_next(stream_id, Segment(pair.key), 0, 0, false, None)
+            .await
+            .map_err(SampleError::from)?;
+    }
+    Ok(keys)
+}
@@ -1,5 +1,12 @@
 #[macro_use]
 extern crate tracing;
 
+pub mod file_location;
+pub mod hedging;
+pub mod indexer;
 pub mod kv_fetcher;
+pub mod node_health;
+pub mod segment_cache;
+pub mod shard;
+pub mod single_flight;
 pub mod zgs_fetcher;
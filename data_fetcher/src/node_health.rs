@@ -0,0 +1,185 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::shard::Shard;
+
+// consecutive failures that open the circuit of a node
+const FAILURE_THRESHOLD: u32 = 5;
+// how long an open circuit rejects requests before a probe is let through
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+// weight of the latest request in the latency and error rate averages
+const EWMA_WEIGHT: f64 = 0.2;
+// latency assumed for a node that has not served a segment yet
+const INITIAL_LATENCY_MS: f64 = 100.0;
+// lower bound of the success rate when scoring, so failing nodes keep a finite score
+const MIN_SUCCESS_RATE: f64 = 0.05;
+
+/// Outcome of a single request to a node
+#[derive(Clone, Copy, Debug)]
+pub enum Outcome {
+    Success(Duration),
+    /// The node answered but does not store the segment
+    Missing,
+    Error,
+    /// The node served data that failed validation
+    InvalidProof,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// Health counters and score of a node, a lower score is better
+#[derive(Clone, Debug)]
+pub struct NodeStats {
+    pub url: String,
+    pub score: f64,
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub requests: u64,
+    pub errors: u64,
+    pub invalid_proofs: u64,
+    pub misses: u64,
+    pub circuit: CircuitState,
+    /// shard of the flow the node stores, `None` if unknown
+    pub shard: Option<Shard>,
+}
+
+enum Circuit {
+    Closed,
+    Open(Instant),
+    // a single probe is in flight since the instant
+    HalfOpen(Instant),
+}
+
+struct State {
+    latency_ms: f64,
+    error_rate: f64,
+    requests: u64,
+    errors: u64,
+    invalid_proofs: u64,
+    misses: u64,
+    consecutive_failures: u32,
+    circuit: Circuit,
+}
+
+impl State {
+    fn score(&self) -> f64 {
+        self.latency_ms / (1.0 - self.error_rate).max(MIN_SUCCESS_RATE)
+    }
+}
+
+/// Latency and error tracking of a storage node with a circuit breaker
+pub struct NodeHealth {
+    state: Mutex<State>,
+}
+
+impl NodeHealth {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                latency_ms: INITIAL_LATENCY_MS,
+                error_rate: 0.0,
+                requests: 0,
+                errors: 0,
+                invalid_proofs: 0,
+                misses: 0,
+                consecutive_failures: 0,
+                circuit: Circuit::Closed,
+            }),
+        }
+    }
+
+    /// Whether `admit` would let a request through, without claiming the probe
+    pub fn available(&self) -> bool {
+        match self.state.lock().expect("lock poisoned").circuit {
+            Circuit::Closed => true,
+            Circuit::Open(since) | Circuit::HalfOpen(since) => since.elapsed() >= OPEN_DURATION,
+        }
+    }
+
+    /// Whether a request may be sent to the node, to be called when the request is sent. Once
+    /// the circuit has been open long enough a single probe is let through.
+    pub fn admit(&self) -> bool {
+        let mut state = self.state.lock().expect("lock poisoned");
+        match state.circuit {
+            Circuit::Closed => true,
+            // a probe that never reported back, e.g. because its request was cancelled, is
+            // replaced after the same delay
+            Circuit::Open(since) | Circuit::HalfOpen(since) if since.elapsed() >= OPEN_DURATION => {
+                state.circuit = Circuit::HalfOpen(Instant::now());
+                true
+            }
+            Circuit::Open(_) | Circuit::HalfOpen(_) => false,
+        }
+    }
+
+    pub fn record(&self, outcome: Outcome) {
+        let mut state = self.state.lock().expect("lock poisoned");
+        state.requests += 1;
+        match outcome {
+            Outcome::Success(elapsed) => {
+                let latency_ms = elapsed.as_secs_f64() * 1000.0;
+                state.latency_ms += EWMA_WEIGHT * (latency_ms - state.latency_ms);
+                state.error_rate -= EWMA_WEIGHT * state.error_rate;
+                state.consecutive_failures = 0;
+                state.circuit = Circuit::Closed;
+            }
+            Outcome::Missing => {
+                // the node is reachable, only the segment is not there
+                state.misses += 1;
+                state.consecutive_failures = 0;
+                state.circuit = Circuit::Closed;
+            }
+            Outcome::Error | Outcome::InvalidProof => {
+                if let Outcome::InvalidProof = outcome {
+                    state.invalid_proofs += 1;
+                } else {
+                    state.errors += 1;
+                }
+                state.error_rate += EWMA_WEIGHT * (1.0 - state.error_rate);
+                state.consecutive_failures += 1;
+                if matches!(state.circuit, Circuit::HalfOpen(_))
+                    || state.consecutive_failures >= FAILURE_THRESHOLD
+                {
+                    state.circuit = Circuit::Open(Instant::now());
+                }
+            }
+        }
+    }
+
+    pub fn score(&self) -> f64 {
+        self.state.lock().expect("lock poisoned").score()
+    }
+
+    pub fn stats(&self, url: &str) -> NodeStats {
+        let state = self.state.lock().expect("lock poisoned");
+        NodeStats {
+            url: url.to_string(),
+            score: state.score(),
+            latency_ms: state.latency_ms,
+            error_rate: state.error_rate,
+            requests: state.requests,
+            errors: state.errors,
+            invalid_proofs: state.invalid_proofs,
+            misses: state.misses,
+            circuit: match state.circuit {
+                Circuit::Closed => CircuitState::Closed,
+                Circuit::Open(_) => CircuitState::Open,
+                Circuit::HalfOpen(_) => CircuitState::HalfOpen,
+            },
+            shard: None,
+        }
+    }
+}
+
+impl Default for NodeHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use ethereum_types::H256;
+use lru::LruCache;
+
+use crate::zgs_fetcher::DownloadedSegment;
+
+/// Hit and miss counts of a cache together with its current size
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct Inner {
+    segments: LruCache<(H256, usize), DownloadedSegment>,
+    bytes: usize,
+}
+
+/// LRU cache of segments whose proofs were validated, keyed by data root and segment index.
+/// The least recently used segments are evicted once the data exceeds `max_bytes`.
+pub struct SegmentCache {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SegmentCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                segments: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached segment, marked as cached and without download time
+    pub fn get(&self, data_root: H256, segment_index: usize) -> Option<DownloadedSegment> {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        match inner.segments.get(&(data_root, segment_index)) {
+            Some(segment) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(DownloadedSegment {
+                    elapsed: Duration::ZERO,
+                    cached: true,
+                    ..segment.clone()
+                })
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Caches a segment, `segment` must have been validated against its proof
+    pub fn insert(&self, data_root: H256, segment_index: usize, segment: DownloadedSegment) {
+        if segment.data.len() > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.bytes += segment.data.len();
+        if let Some(old) = inner.segments.put((data_root, segment_index), segment) {
+            inner.bytes -= old.data.len();
+        }
+        while inner.bytes > self.max_bytes {
+            match inner.segments.pop_lru() {
+                Some((_, evicted)) => inner.bytes -= evicted.data.len(),
+                None => break,
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().expect("lock poisoned");
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.segments.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::Mutex, task::JoinSet};
+use zgs_rpc::ZgsRPCClient;
+
+use crate::zgs_fetcher::ZgsNode;
+
+// how often the shard configuration of the nodes is fetched again
+const SHARD_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Shard of the flow stored by a node: the flow segments whose index modulo `count` is `id`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub id: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    pub fn covers(&self, flow_segment_index: usize) -> bool {
+        self.count <= 1 || flow_segment_index % self.count == self.id
+    }
+}
+
+/// Shard configuration of every node, used to send each segment only to the nodes storing it
+pub struct ShardTracker {
+    // `None` until fetched, or if the node did not answer
+    shards: RwLock<Vec<Option<Shard>>>,
+    // held while refreshing, so concurrent downloads do not refresh as well
+    refreshed_at: Mutex<Option<Instant>>,
+    coverage_gaps: AtomicU64,
+}
+
+impl ShardTracker {
+    pub fn new(nodes: usize) -> Self {
+        Self {
+            shards: RwLock::new(vec![None; nodes]),
+            refreshed_at: Mutex::new(None),
+            coverage_gaps: AtomicU64::new(0),
+        }
+    }
+
+    /// Fetches the shard configuration of the nodes if the last fetch is too old, nodes not
+    /// given, e.g. the ones discovered through the indexer, keep their shards. Downloads
+    /// arriving while another one refreshes go on with the shards known so far. The fetches
+    /// run in a `JoinSet`, so they stop if this future is dropped, and the refresh is only
+    /// recorded once they are done.
+    pub async fn refresh(&self, nodes: &[ZgsNode]) {
+        let Ok(mut refreshed_at) = self.refreshed_at.try_lock() else {
+            return;
+        };
+        if refreshed_at.is_some_and(|at| at.elapsed() < SHARD_REFRESH_INTERVAL) {
+            return;
+        }
+
+        let mut fetches = JoinSet::new();
+        for (i, node) in nodes.iter().enumerate() {
+            let client = node.client.clone();
+            fetches.spawn(async move { (i, client.get_shard_config().await) });
+        }
+        // a node that does not answer keeps the shard it was known with
+        let mut shards = self.shards();
+        if shards.len() < nodes.len() {
+            shards.resize(nodes.len(), None);
+        }
+        while let Some(joined) = fetches.join_next().await {
+            match joined {
+                Ok((i, Ok(config))) => {
+                    shards[i] = Some(Shard {
+                        id: config.shard_id,
+                        count: config.num_shard,
+                    })
+                }
+                Ok((i, Err(e))) => {
+                    debug!("Get shard config from {:?} failed: {:?}", nodes[i].url, e)
+                }
+                Err(e) => error!("get shard config task failed: {:?}", e),
+            }
+        }
+        info!("node shards {:?}", shards);
+        *self.shards.write().expect("lock poisoned") = shards;
+        *refreshed_at = Some(Instant::now());
+    }
+
+    /// Sets the shard of a node, e.g. as told by the indexer
+    pub fn set(&self, index: usize, shard: Shard) {
+        let mut shards = self.shards.write().expect("lock poisoned");
+        if shards.len() <= index {
+            shards.resize(index + 1, None);
+        }
+        shards[index] = Some(shard);
+    }
+
+    pub fn shards(&self) -> Vec<Option<Shard>> {
+        self.shards.read().expect("lock poisoned").clone()
+    }
+
+    /// Nodes among `candidates` whose shard covers the flow segment, nodes of unknown shard
+    /// are assumed to cover it. All candidates are returned if none covers it, as shards may
+    /// have changed since the last refresh.
+    pub fn route(&self, candidates: &[usize], flow_segment_index: usize) -> Vec<usize> {
+        let shards = self.shards.read().expect("lock poisoned");
+        let covers = |i: &usize| {
+            shards
+                .get(*i)
+                .copied()
+                .flatten()
+                .map_or(true, |x| x.covers(flow_segment_index))
+        };
+        let route: Vec<usize> = candidates.iter().copied().filter(covers).collect();
+        if !route.is_empty() {
+            return route;
+        }
+        if !(0..shards.len()).any(|i| covers(&i)) {
+            self.coverage_gaps.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Flow segment {:?} is not covered by any node shard",
+                flow_segment_index
+            );
+        }
+        candidates.to_vec()
+    }
+
+    /// Segments routed while no configured node's shard covered them
+    pub fn coverage_gaps(&self) -> u64 {
+        self.coverage_gaps.load(Ordering::Relaxed)
+    }
+}
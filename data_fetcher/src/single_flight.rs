@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+
+type Flights<K, V> = Arc<Mutex<HashMap<K, watch::Receiver<Option<V>>>>>;
+
+/// Deduplicates concurrent work on the same key: the first caller leads the work and later
+/// callers wait for its value instead of doing the work again
+pub struct SingleFlight<K, V> {
+    flights: Flights<K, V>,
+}
+
+pub enum Flight<K: Eq + Hash, V> {
+    /// No work is in flight for the key, the caller must do it and complete the leader
+    Leader(Leader<K, V>),
+    /// Work is in flight for the key, the caller waits for its value
+    Follower(Follower<V>),
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            flights: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn join(&self, key: K) -> Flight<K, V> {
+        let mut flights = self.flights.lock().expect("lock poisoned");
+        if let Some(rx) = flights.get(&key) {
+            return Flight::Follower(Follower { rx: rx.clone() });
+        }
+        let (tx, rx) = watch::channel(None);
+        flights.insert(key.clone(), rx);
+        Flight::Leader(Leader {
+            key,
+            tx,
+            flights: self.flights.clone(),
+        })
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle of the caller doing the work for a key. Dropping it without completing, e.g. when
+/// the leading request is cancelled or fails, releases the key and wakes the followers.
+pub struct Leader<K: Eq + Hash, V> {
+    key: K,
+    tx: watch::Sender<Option<V>>,
+    flights: Flights<K, V>,
+}
+
+impl<K: Eq + Hash, V> Leader<K, V> {
+    /// Hands the value to every follower and releases the key
+    pub fn complete(self, value: V) {
+        self.tx.send_replace(Some(value));
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<K, V> {
+    fn drop(&mut self) {
+        // the key is released before the sender is dropped, so a woken follower joining again
+        // never finds the abandoned flight
+        self.flights
+            .lock()
+            .expect("lock poisoned")
+            .remove(&self.key);
+    }
+}
+
+pub struct Follower<V> {
+    rx: watch::Receiver<Option<V>>,
+}
+
+impl<V: Clone> Follower<V> {
+    /// Waits for the leader, `None` if it was dropped without completing, the caller should
+    /// join again then
+    pub async fn wait(mut self) -> Option<V> {
+        loop {
+            if let Some(value) = self.rx.borrow_and_update().clone() {
+                return Some(value);
+            }
+            if self.rx.changed().await.is_err() {
+                return self.rx.borrow().clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lead(flights: &SingleFlight<u32, u32>, key: u32) -> Leader<u32, u32> {
+        match flights.join(key) {
+            Flight::Leader(x) => x,
+            Flight::Follower(_) => panic!("joined as follower"),
+        }
+    }
+
+    fn follow(flights: &SingleFlight<u32, u32>, key: u32) -> Follower<u32> {
+        match flights.join(key) {
+            Flight::Leader(_) => panic!("joined as leader"),
+            Flight::Follower(x) => x,
+        }
+    }
+
+    #[tokio::test]
+    async fn completed_value_reaches_every_follower() {
+        let flights = SingleFlight::new();
+        let leader = lead(&flights, 1);
+        let waiting = tokio::spawn(follow(&flights, 1).wait());
+        let late = follow(&flights, 1);
+        // another key is not joined to the flight
+        drop(lead(&flights, 2));
+        tokio::task::yield_now().await;
+
+        leader.complete(7);
+        assert_eq!(waiting.await.unwrap(), Some(7));
+        assert_eq!(late.wait().await, Some(7));
+        // the key is released once completed
+        lead(&flights, 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_leader_wakes_followers_and_releases_the_key() {
+        let flights = SingleFlight::new();
+        let leader = lead(&flights, 1);
+        let waiting = tokio::spawn(follow(&flights, 1).wait());
+        tokio::task::yield_now().await;
+
+        drop(leader);
+        assert_eq!(waiting.await.unwrap(), None);
+        let leader = lead(&flights, 1);
+        let follower = follow(&flights, 1);
+        leader.complete(3);
+        assert_eq!(follower.wait().await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn dropped_follower_does_not_affect_the_leader() {
+        let flights = SingleFlight::new();
+        let leader = lead(&flights, 1);
+        drop(follow(&flights, 1));
+        tokio::spawn(follow(&flights, 1).wait()).abort();
+        tokio::task::yield_now().await;
+
+        let follower = follow(&flights, 1);
+        leader.complete(5);
+        assert_eq!(follower.wait().await, Some(5));
+    }
+}
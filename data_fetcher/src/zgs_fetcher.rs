@@ -1,142 +1,670 @@
 use std::collections::HashMap;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Result};
+use common::error::SampleError;
 use ethereum_types::H256;
 use jsonrpsee::http_client::HttpClient;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use kv_rpc::build_client;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 use zgs_rpc::ZgsRPCClient;
 
+use crate::{
+    file_location::FileLocator,
+    hedging::{HedgeStats, Hedging},
+    indexer::IndexerClient,
+    node_health::{NodeHealth, NodeStats, Outcome},
+    segment_cache::{CacheStats, SegmentCache},
+    shard::ShardTracker,
+    single_flight::{Flight, SingleFlight},
+};
+
 const MAX_DOWNLOAD_TASK: usize = 5;
 const MAX_RETRY: usize = 5;
 pub const ENTRY_SIZE: usize = 256;
 pub const ENTRIES_PER_SEGMENT: usize = 1024;
+// wait before downloading a segment again once every node failed it
 const RETRY_WAIT_MS: u64 = 1000;
 
-pub async fn download_segments(
-    clients: Vec<HttpClient>,
-    data_root: H256,
-    segment_indexes: Vec<usize>,
-) -> Result<Vec<Vec<u8>>> {
-    let mut task_counter = 0;
-    let mut task_index = 0;
-    let (sender, mut rx) = unbounded_channel();
-    while task_index < segment_indexes.len() && task_counter < MAX_DOWNLOAD_TASK {
-        tokio::spawn(download_with_proof(
-            task_index,
-            clients.clone(),
-            data_root,
-            segment_indexes[task_index],
-            sender.clone(),
-        ));
-        task_index += 1;
-        task_counter += 1;
+#[derive(Clone)]
+pub struct ZgsNode {
+    pub url: String,
+    pub client: HttpClient,
+    pub health: Arc<NodeHealth>,
+}
+
+impl ZgsNode {
+    pub fn new(url: &String) -> Result<Self> {
+        Ok(Self {
+            url: url.clone(),
+            client: build_client(url).map_err(|e| anyhow!(e.to_string()))?,
+            health: Arc::new(NodeHealth::new()),
+        })
     }
-    let mut result = vec![vec![]; segment_indexes.len()];
-    let mut failed_tasks = HashMap::new();
-    while task_index < segment_indexes.len() || task_counter > 0 {
-        if let Some((id, maybe_data)) = rx.recv().await {
-            match maybe_data {
-                Some(data) => {
-                    result[id] = data;
-                    if task_index < segment_indexes.len() {
-                        tokio::spawn(download_with_proof(
-                            task_index,
-                            clients.clone(),
-                            data_root,
-                            segment_indexes[task_index],
-                            sender.clone(),
-                        ));
-                        task_index += 1;
-                    } else {
-                        task_counter -= 1;
+}
+
+/// Segment data with its proof validated, together with the node that served it
+#[derive(Clone, Debug)]
+pub struct DownloadedSegment {
+    pub data: Vec<u8>,
+    pub node: String,
+    /// time the download took, zero if served from the cache
+    pub elapsed: Duration,
+    /// served from the segment cache, `node` is the node that served it first
+    pub cached: bool,
+}
+
+/// Storage nodes to download segments from, with a cache of the segments already downloaded
+#[derive(Clone)]
+pub struct ZgsFetcher {
+    // configured nodes first, then the nodes discovered through the indexer, nodes are never
+    // removed so their indexes stay valid
+    nodes: Arc<RwLock<Vec<ZgsNode>>>,
+    // number of configured nodes, discovered nodes are only asked for the files the indexer
+    // reports them holding
+    configured: usize,
+    indexer: Option<Arc<IndexerClient>>,
+    // time allowed to download the segments of one call
+    download_timeout: Option<Duration>,
+    cache: Arc<SegmentCache>,
+    hedging: Arc<Hedging>,
+    // nodes holding the file of each data root, downloads only go to them
+    locator: Arc<FileLocator>,
+    // shard of the flow stored by each node, segments only go to the nodes storing them
+    shards: Arc<ShardTracker>,
+    // segments being downloaded, shared by concurrent requests
+    flights: Arc<SingleFlight<(H256, usize), Option<DownloadedSegment>>>,
+}
+
+impl ZgsFetcher {
+    /// `cache_bytes` bounds the segment data kept in the cache, 0 disables the cache. Nodes
+    /// holding a file are also asked to the indexer if `indexer_url` is set. Downloads still
+    /// running after `download_timeout` are stopped.
+    pub fn new(
+        urls: &[String],
+        cache_bytes: usize,
+        indexer_url: Option<&str>,
+        download_timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let nodes = urls.iter().map(ZgsNode::new).collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            shards: Arc::new(ShardTracker::new(nodes.len())),
+            configured: nodes.len(),
+            nodes: Arc::new(RwLock::new(nodes)),
+            indexer: indexer_url
+                .map(|url| IndexerClient::new(url).map(Arc::new))
+                .transpose()?,
+            download_timeout,
+            cache: Arc::new(SegmentCache::new(cache_bytes)),
+            hedging: Arc::new(Hedging::new()),
+            locator: Arc::new(FileLocator::new()),
+            flights: Arc::new(SingleFlight::new()),
+        })
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn hedge_stats(&self) -> HedgeStats {
+        self.hedging.stats()
+    }
+
+    pub fn node_stats(&self) -> Vec<NodeStats> {
+        let shards = self.shards.shards();
+        self.nodes()
+            .iter()
+            .enumerate()
+            .map(|(i, node)| NodeStats {
+                shard: shards.get(i).copied().flatten(),
+                ..node.health.stats(&node.url)
+            })
+            .collect()
+    }
+
+    fn nodes(&self) -> Vec<ZgsNode> {
+        self.nodes.read().expect("lock poisoned").clone()
+    }
+
+    /// Asks the indexer which nodes hold the file of the data root and adds the ones not
+    /// known yet, returns the indexes of the nodes found
+    pub async fn discover(&self, data_root: H256) -> Result<Vec<usize>> {
+        let Some(indexer) = &self.indexer else {
+            return Ok(vec![]);
+        };
+        let found = indexer.file_locations(data_root).await?;
+        let mut nodes = self.nodes.write().expect("lock poisoned");
+        let mut indexes = vec![];
+        for found in found {
+            let index = match nodes.iter().position(|x| x.url == found.url) {
+                Some(index) => index,
+                None => {
+                    match ZgsNode::new(&found.url) {
+                        Ok(node) => nodes.push(node),
+                        Err(e) => {
+                            warn!("Invalid node url {:?} from indexer: {:?}", found.url, e);
+                            continue;
+                        }
                     }
+                    info!(
+                        "discovered storage node {:?} through indexer {:?}",
+                        found.url,
+                        indexer.url()
+                    );
+                    nodes.len() - 1
                 }
-                None => {
-                    match failed_tasks.get_mut(&id) {
-                        Some(c) => {
-                            if *c == MAX_RETRY {
-                                bail!(anyhow!(format!(
-                                    "Download segment with index {:?} failed, data root: {:x?}",
-                                    segment_indexes[id], data_root,
-                                )));
+            };
+            self.shards.set(index, found.shard());
+            indexes.push(index);
+        }
+        Ok(indexes)
+    }
+
+    /// Segments that no configured node's shard covered
+    pub fn coverage_gaps(&self) -> u64 {
+        self.shards.coverage_gaps()
+    }
+
+    /// Downloads all segments, segments that cannot be downloaded after retries are left as
+    /// `None`
+    pub async fn try_download_segments(
+        &self,
+        data_root: H256,
+        segment_indexes: Vec<usize>,
+    ) -> Result<Vec<Option<DownloadedSegment>>> {
+        self.download(data_root, segment_indexes, |_, _| {}).await
+    }
+
+    /// Same as [`ZgsFetcher::try_download_segments`], `on_segment` is called with the position
+    /// of each segment in `segment_indexes` as soon as it is downloaded or given up
+    pub async fn try_download_segments_with(
+        &self,
+        data_root: H256,
+        segment_indexes: Vec<usize>,
+        on_segment: impl FnMut(usize, Option<&DownloadedSegment>) + Send,
+    ) -> Result<Vec<Option<DownloadedSegment>>> {
+        self.download(data_root, segment_indexes, on_segment).await
+    }
+
+    /// Downloads the segments within the download timeout, which covers the whole call: the
+    /// lookups of the nodes holding the file and the waits on concurrent downloads as well.
+    /// Everything the call started stops once it times out or is dropped.
+    async fn download(
+        &self,
+        data_root: H256,
+        segment_indexes: Vec<usize>,
+        on_segment: impl FnMut(usize, Option<&DownloadedSegment>) + Send,
+    ) -> Result<Vec<Option<DownloadedSegment>>> {
+        let rounds = self.download_rounds(data_root, segment_indexes, on_segment);
+        let Some(timeout) = self.download_timeout else {
+            return rounds.await;
+        };
+        match tokio::time::timeout(timeout, rounds).await {
+            Ok(result) => result,
+            Err(_) => bail!(SampleError::UpstreamTimeout(format!(
+                "segment download deadline passed, data root: {:?}",
+                data_root
+            ))),
+        }
+    }
+
+    /// Serves the segments found in the cache and downloads the others, caching them once
+    /// their proofs are validated. Segments already being downloaded by a concurrent request
+    /// are awaited instead of downloaded again.
+    async fn download_rounds(
+        &self,
+        data_root: H256,
+        segment_indexes: Vec<usize>,
+        mut on_segment: impl FnMut(usize, Option<&DownloadedSegment>) + Send,
+    ) -> Result<Vec<Option<DownloadedSegment>>> {
+        let mut result = vec![None; segment_indexes.len()];
+        let mut pending: Vec<usize> = (0..segment_indexes.len()).collect();
+        while !pending.is_empty() {
+            let mut leading = vec![];
+            let mut following = vec![];
+            for i in pending.drain(..) {
+                if let Some(segment) = self.cache.get(data_root, segment_indexes[i]) {
+                    on_segment(i, Some(&segment));
+                    result[i] = Some(segment);
+                    continue;
+                }
+                match self.flights.join((data_root, segment_indexes[i])) {
+                    Flight::Leader(leader) => leading.push((i, Some(leader))),
+                    Flight::Follower(follower) => following.push((i, follower)),
+                }
+            }
+
+            if !leading.is_empty() {
+                let location = match self.locator.cached(data_root) {
+                    Some(location) => location,
+                    None => {
+                        // the configured nodes are asked, and of the discovered nodes only the
+                        // ones the indexer reports for this file
+                        let mut asked: Vec<usize> = (0..self.configured).collect();
+                        match self.discover(data_root).await {
+                            Ok(found) => {
+                                asked.extend(found.into_iter().filter(|x| *x >= self.configured))
                             }
-                            *c += 1;
+                            Err(e) => warn!("Discover nodes of {:x?} failed: {:?}", data_root, e),
                         }
-                        _ => {
-                            failed_tasks.insert(id, 1);
+                        asked.sort_unstable();
+                        asked.dedup();
+                        self.locator.locate(&self.nodes(), asked, data_root).await
+                    }
+                };
+                let nodes = self.nodes();
+                if location.not_finalized() {
+                    bail!(SampleError::FileNotFinalized { data_root });
+                }
+                // when no node reports the finalized file, e.g. because the lookups failed,
+                // every node asked is tried
+                let candidates: Vec<usize> = if location.holders.is_empty() {
+                    location.asked.clone()
+                } else {
+                    location.holders.clone()
+                };
+                self.shards.refresh(&nodes[..self.configured]).await;
+                let routes = leading
+                    .iter()
+                    .map(|(i, _)| {
+                        let route = match location.start_entry_index {
+                            Some(start_entry_index) => self.shards.route(
+                                &candidates,
+                                start_entry_index as usize / ENTRIES_PER_SEGMENT
+                                    + segment_indexes[*i],
+                            ),
+                            None => candidates.clone(),
+                        };
+                        route.iter().map(|j| nodes[*j].clone()).collect()
+                    })
+                    .collect();
+                let indexes = leading.iter().map(|(i, _)| segment_indexes[*i]).collect();
+                // leaders are completed as soon as their segment is done, a failed download
+                // drops the remaining ones so their followers download by themselves
+                let downloaded = download(
+                    routes,
+                    self.hedging.clone(),
+                    data_root,
+                    indexes,
+                    |j, segment| {
+                        let (i, leader) = &mut leading[j];
+                        if let Some(segment) = segment {
+                            self.cache
+                                .insert(data_root, segment_indexes[*i], segment.clone());
                         }
-                    };
-                    // TODO: request new file
-                    tokio::spawn(download_with_proof(
-                        id,
-                        clients.clone(),
-                        data_root,
-                        segment_indexes[id],
-                        sender.clone(),
-                    ));
+                        if let Some(leader) = leader.take() {
+                            leader.complete(segment.cloned());
+                        }
+                        on_segment(*i, segment);
+                    },
+                )
+                .await?;
+                for (j, segment) in downloaded.into_iter().enumerate() {
+                    result[leading[j].0] = segment;
+                }
+            }
+
+            for (i, follower) in following {
+                match follower.wait().await {
+                    Some(Some(segment)) => {
+                        on_segment(i, Some(&segment));
+                        result[i] = Some(segment);
+                    }
+                    Some(None) => on_segment(i, None),
+                    // the leading request was dropped before finishing the segment
+                    None => pending.push(i),
                 }
             }
         }
+        debug!("segment cache {:?}", self.cache.stats());
+        Ok(result)
     }
-    Ok(result)
 }
 
-async fn download_with_proof(
-    task_index: usize,
-    clients: Vec<HttpClient>,
+/// Downloads each segment from the nodes of its route. The downloads run in a `JoinSet` and
+/// observe a cancellation token, so none outlives this future: they stop once it is dropped
+/// or a download task fails.
+async fn download(
+    routes: Vec<Vec<ZgsNode>>,
+    hedging: Arc<Hedging>,
     data_root: H256,
-    segment_index: usize,
-    sender: UnboundedSender<(usize, Option<Vec<u8>>)>,
-) {
-    let mut client_index = 0;
-    while client_index < clients.len() {
-        match clients[client_index]
-            .download_segment_with_proof(data_root, segment_index)
-            .await
-        {
-            Ok(Some(segment)) => {
-                if segment.data.len() % ENTRY_SIZE != 0 {
-                    if let Err(e) = sender.send((task_index, None)) {
-                        error!("send error: {:?}", e);
-                    }
+    segment_indexes: Vec<usize>,
+    mut on_segment: impl FnMut(usize, Option<&DownloadedSegment>) + Send,
+) -> Result<Vec<Option<DownloadedSegment>>> {
+    let cancel = CancellationToken::new();
+    // cancels the downloads however this future ends, including being dropped, dropping the
+    // join set also aborts them
+    let _cancel_on_drop = cancel.clone().drop_guard();
+    let mut tasks = JoinSet::new();
+    let spawn =
+        |tasks: &mut JoinSet<(usize, Option<DownloadedSegment>)>, id: usize, delay: Duration| {
+            let cancel = cancel.clone();
+            let task = download_with_proof(
+                routes[id].clone(),
+                hedging.clone(),
+                data_root,
+                segment_indexes[id],
+                delay,
+            );
+            tasks.spawn(async move {
+                tokio::select! {
+                    segment = task => (id, segment),
+                    _ = cancel.cancelled() => (id, None),
+                }
+            });
+        };
 
-                    return;
+    let mut task_index = 0;
+    while task_index < segment_indexes.len() && tasks.len() < MAX_DOWNLOAD_TASK {
+        spawn(&mut tasks, task_index, Duration::ZERO);
+        task_index += 1;
+    }
+    let mut result = vec![None; segment_indexes.len()];
+    let mut failed_tasks = HashMap::new();
+    loop {
+        let Some(joined) = tasks.join_next().await else {
+            break;
+        };
+        let (id, maybe_data) = match joined {
+            Ok(joined) => joined,
+            Err(e) => {
+                stop(&cancel, &mut tasks).await;
+                bail!("segment download task failed: {:?}", e);
+            }
+        };
+        match maybe_data {
+            Some(segment) => {
+                on_segment(id, Some(&segment));
+                result[id] = Some(segment);
+            }
+            None => {
+                let retries = failed_tasks.entry(id).or_insert(0);
+                if *retries < MAX_RETRY {
+                    *retries += 1;
+                    spawn(&mut tasks, id, Duration::from_millis(RETRY_WAIT_MS));
+                    continue;
                 }
+                warn!(
+                    "Segment with index {:?} unavailable, data root: {:x?}",
+                    segment_indexes[id], data_root,
+                );
+                on_segment(id, None);
+            }
+        }
+        if task_index < segment_indexes.len() {
+            spawn(&mut tasks, task_index, Duration::ZERO);
+            task_index += 1;
+        }
+    }
+    Ok(result)
+}
 
-                if segment.root != data_root {
-                    if let Err(e) = sender.send((task_index, None)) {
-                        error!("send error: {:?}", e);
-                    }
+/// Cancels the outstanding downloads and waits for them to stop
+async fn stop<T: 'static>(cancel: &CancellationToken, tasks: &mut JoinSet<T>) {
+    cancel.cancel();
+    while tasks.join_next().await.is_some() {}
+}
 
-                    return;
-                }
+/// Indexes of the nodes to try, healthiest first, and whether their circuits must admit the
+/// attempts. Nodes with an open circuit are skipped unless every circuit is open, trying them
+/// is better than failing right away then. Circuits are only peeked here, ranking a node that
+/// is never tried must not claim its probe.
+fn rank_nodes(clients: &[ZgsNode]) -> (Vec<usize>, bool) {
+    let mut ranked: Vec<(usize, f64)> = clients
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.health.available())
+        .map(|(i, node)| (i, node.health.score()))
+        .collect();
+    let admit = !ranked.is_empty();
+    if !admit {
+        ranked = clients
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (i, node.health.score()))
+            .collect();
+    }
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+    (ranked.into_iter().map(|(i, _)| i).collect(), admit)
+}
 
-                if segment.validate(ENTRIES_PER_SEGMENT).is_err() {
-                    if let Err(e) = sender.send((task_index, None)) {
-                        error!("send error: {:?}", e);
+async fn download_with_proof(
+    clients: Vec<ZgsNode>,
+    hedging: Arc<Hedging>,
+    data_root: H256,
+    segment_index: usize,
+    delay: Duration,
+) -> Option<DownloadedSegment> {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    let start = Instant::now();
+    let (ranked, admit) = rank_nodes(&clients);
+    hedging.record_request();
+
+    // nodes are asked one after another, except that a second node is asked in parallel once
+    // the first one is late, the first valid segment wins. Returning drops the join set, which
+    // aborts the slower request of a hedged pair.
+    let mut attempts = JoinSet::new();
+    let mut next = 0;
+    let mut may_hedge = true;
+    let mut hedge_node = None;
+    loop {
+        if attempts.is_empty() {
+            let Some(&client_index) = ranked.get(next) else {
+                break;
+            };
+            next += 1;
+            // a node whose probe was claimed meanwhile is skipped, the loop moves on to the
+            // next one
+            spawn_attempt(
+                &mut attempts,
+                &clients,
+                client_index,
+                admit,
+                &hedging,
+                data_root,
+                segment_index,
+            );
+        }
+        let joined = if may_hedge && next < ranked.len() {
+            match tokio::time::timeout(hedging.delay(), attempts.join_next()).await {
+                Ok(joined) => joined,
+                Err(_) => {
+                    may_hedge = false;
+                    if hedging.try_hedge() {
+                        let client_index = ranked[next];
+                        debug!(
+                            "Hedging segment {:?} to {:?}, data root: {:x?}",
+                            segment_index, clients[client_index].url, data_root
+                        );
+                        if spawn_attempt(
+                            &mut attempts,
+                            &clients,
+                            client_index,
+                            admit,
+                            &hedging,
+                            data_root,
+                            segment_index,
+                        ) {
+                            hedge_node = Some(client_index);
+                        }
+                        next += 1;
                     }
-                    return;
+                    continue;
                 }
+            }
+        } else {
+            attempts.join_next().await
+        };
+        let Some(joined) = joined else {
+            continue;
+        };
+        let (client_index, data) = match joined {
+            Ok(joined) => joined,
+            Err(e) => {
+                error!("segment download attempt failed: {:?}", e);
+                continue;
+            }
+        };
+        if let Some(data) = data {
+            if hedge_node == Some(client_index) {
+                hedging.record_win();
+            }
+            return Some(DownloadedSegment {
+                data,
+                node: clients[client_index].url.clone(),
+                elapsed: start.elapsed(),
+                cached: false,
+            });
+        }
+    }
+    None
+}
 
-                if let Err(e) = sender.send((task_index, Some(segment.data))) {
-                    error!("send error: {:?}", e);
+/// Asks a single node for the segment, the validated data is returned with the node index.
+/// If `admit` is set the attempt is only started if the circuit of the node admits it,
+/// returns whether it was started.
+fn spawn_attempt(
+    attempts: &mut JoinSet<(usize, Option<Vec<u8>>)>,
+    clients: &[ZgsNode],
+    client_index: usize,
+    admit: bool,
+    hedging: &Arc<Hedging>,
+    data_root: H256,
+    segment_index: usize,
+) -> bool {
+    let node = clients[client_index].clone();
+    if admit && !node.health.admit() {
+        return false;
+    }
+    let hedging = hedging.clone();
+    attempts.spawn(async move {
+        let request_start = Instant::now();
+        // rows are sliced out of whole segments, there is no ranged download: storage nodes
+        // only prove whole segments, `downloadSegment` serves an entry range without any proof
+        // against the data root and `downloadSegmentWithProof` takes a segment index. Fetching
+        // only the entries of a row needs an RPC proving an entry range first.
+        let data = match node
+            .client
+            .download_segment_with_proof(data_root, segment_index)
+            .await
+        {
+            Ok(Some(segment)) => {
+                if segment.data.len() % ENTRY_SIZE != 0
+                    || segment.root != data_root
+                    || segment.validate(ENTRIES_PER_SEGMENT).is_err()
+                {
+                    warn!(
+                        "Invalid segment {:?} from {:?}, data root: {:x?}",
+                        segment_index, node.url, data_root
+                    );
+                    node.health.record(Outcome::InvalidProof);
+                    None
+                } else {
+                    let elapsed = request_start.elapsed();
+                    node.health.record(Outcome::Success(elapsed));
+                    hedging.record_latency(elapsed);
+                    Some(segment.data)
                 }
-
-                return;
             }
             Ok(None) => {
-                client_index += 1;
-                tokio::time::sleep(Duration::from_millis(RETRY_WAIT_MS)).await;
+                node.health.record(Outcome::Missing);
+                None
             }
-            Err(_e) => {
-                client_index += 1;
-                tokio::time::sleep(Duration::from_millis(RETRY_WAIT_MS)).await;
+            Err(e) => {
+                debug!("Download from {:?} failed: {:?}", node.url, e);
+                node.health.record(Outcome::Error);
+                None
             }
-        }
+        };
+        (client_index, data)
+    });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use jsonrpsee::{
+        core::Error,
+        http_server::{HttpServerBuilder, HttpServerHandle},
+        RpcModule,
+    };
+
+    use super::*;
+
+    /// Storage node that knows no file and has no segment, it answers file info requests after
+    /// `file_info_delay` and segment downloads after `download_delay`
+    async fn stub_node(
+        file_info_delay: Duration,
+        download_delay: Duration,
+    ) -> (String, HttpServerHandle) {
+        let server = HttpServerBuilder::default()
+            .build("127.0.0.1:0".parse::<SocketAddr>().unwrap())
+            .await
+            .unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let mut module = RpcModule::new((file_info_delay, download_delay));
+        module
+            .register_async_method("zgs_getFileInfo", |_, delays| async move {
+                tokio::time::sleep(delays.0).await;
+                Ok::<_, Error>(None::<()>)
+            })
+            .unwrap();
+        module
+            .register_async_method("zgs_downloadSegmentWithProof", |_, delays| async move {
+                tokio::time::sleep(delays.1).await;
+                Ok::<_, Error>(None::<()>)
+            })
+            .unwrap();
+        (url, server.start(module).unwrap())
+    }
+
+    #[tokio::test]
+    async fn deadline_covers_file_lookups() {
+        let (url, _handle) = stub_node(Duration::from_secs(5), Duration::ZERO).await;
+        let fetcher = ZgsFetcher::new(&[url], 0, None, Some(Duration::from_millis(100))).unwrap();
+
+        let start = Instant::now();
+        let e = fetcher
+            .try_download_segments(H256::repeat_byte(1), vec![0])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<SampleError>(),
+            Some(SampleError::UpstreamTimeout(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
     }
 
-    if let Err(e) = sender.send((task_index, None)) {
-        error!("send error: {:?}", e);
+    #[tokio::test]
+    async fn deadline_stops_downloads() {
+        let (url, _handle) = stub_node(Duration::ZERO, Duration::from_millis(300)).await;
+        let fetcher = ZgsFetcher::new(&[url], 0, None, Some(Duration::from_millis(100))).unwrap();
+
+        let result = fetcher
+            .try_download_segments(H256::repeat_byte(1), vec![0, 1])
+            .await;
+        assert!(result.is_err());
+        // a download still running would record the answer of the node
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(fetcher.node_stats()[0].requests, 0);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_call_stops_downloads() {
+        let (url, _handle) = stub_node(Duration::ZERO, Duration::from_millis(300)).await;
+        let fetcher = ZgsFetcher::new(&[url], 0, None, None).unwrap();
+
+        let call = fetcher.try_download_segments(H256::repeat_byte(1), vec![0, 1]);
+        assert!(tokio::time::timeout(Duration::from_millis(100), call)
+            .await
+            .is_err());
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(fetcher.node_stats()[0].requests, 0);
     }
 }
@@ -1,19 +1,23 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use sampler::Sampler;
 use service::{light::light_server::LightServer, LightService};
+use store::SampleStore;
 use tonic::transport::Server;
 
 #[macro_use]
 extern crate tracing;
 
 mod service;
+mod status;
+mod validate;
 
 pub async fn run_server(
     addr: SocketAddr,
-    sampler: Sampler,
+    sampler: Arc<Sampler>,
+    store: Option<Arc<SampleStore>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let encoder_service = LightService::new(sampler);
+    let encoder_service = LightService::new(sampler, store);
     Server::builder()
         .add_service(LightServer::new(encoder_service))
         .serve(addr)
@@ -1,22 +1,167 @@
-use ethereum_types::H256;
-use sampler::Sampler;
+use std::sync::Arc;
+
+use sampler::{CellEvent, CellVerdict, CircuitState, Position, SampleReport, Sampler};
+use store::{SampleStore, Verdict};
+use tokio::sync::mpsc::{self, unbounded_channel};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Code, Request, Response, Status};
 
+use crate::{
+    status::{error_code, error_status},
+    validate::{
+        ListByBatchParams, ListByTimeParams, RetrieveParams, SampleBatchParams, SampleParams,
+    },
+};
+
 use self::light::{
-    light_server::Light, RetrieveReply, RetrieveRequest, SampleReply, SampleRequest,
+    light_server::Light, sample_event::Event, BatchCacheStats, BlobSampleResult, CellPosition,
+    CellReport, GetStatsReply, GetStatsRequest, HedgeStats, ListSamplesByBatchRequest,
+    ListSamplesByTimeRequest, ListSamplesReply, NodeStats, RetrieveReply, RetrieveRequest,
+    SampleBatchReply, SampleBatchRequest, SampleEvent, SampleRecord, SampleReply, SampleRequest,
+    SegmentCacheStats,
 };
 
 pub mod light {
     tonic::include_proto!("light");
 }
 
+// events buffered for a slow streaming client before sampling waits for it
+const EVENT_BUFFER_SIZE: usize = 64;
+
 pub struct LightService {
-    sampler: Sampler,
+    sampler: Arc<Sampler>,
+    store: Option<Arc<SampleStore>>,
 }
 
 impl LightService {
-    pub fn new(sampler: Sampler) -> Self {
-        Self { sampler }
+    pub fn new(sampler: Arc<Sampler>, store: Option<Arc<SampleStore>>) -> Self {
+        Self { sampler, store }
+    }
+
+    fn store(&self) -> Result<&SampleStore, Status> {
+        self.store
+            .as_deref()
+            .ok_or_else(|| Status::new(Code::FailedPrecondition, "sample store disabled"))
+    }
+}
+
+impl From<Position> for CellPosition {
+    fn from(position: Position) -> Self {
+        Self {
+            row: position.row,
+            col: position.col.into(),
+        }
+    }
+}
+
+impl From<sampler::NodeStats> for NodeStats {
+    fn from(node: sampler::NodeStats) -> Self {
+        let circuit = match node.circuit {
+            CircuitState::Closed => light::CircuitState::Closed,
+            CircuitState::Open => light::CircuitState::Open,
+            CircuitState::HalfOpen => light::CircuitState::HalfOpen,
+        };
+        Self {
+            url: node.url,
+            score: node.score,
+            latency_ms: node.latency_ms,
+            error_rate: node.error_rate,
+            requests: node.requests,
+            errors: node.errors,
+            invalid_proofs: node.invalid_proofs,
+            misses: node.misses,
+            circuit: circuit.into(),
+            shard_id: node.shard.map_or(0, |x| x.id as u64),
+            num_shard: node.shard.map_or(0, |x| x.count as u64),
+        }
+    }
+}
+
+impl From<sampler::CellReport> for CellReport {
+    fn from(cell: sampler::CellReport) -> Self {
+        let verdict = match cell.verdict {
+            CellVerdict::Verified => light::CellVerdict::Verified,
+            CellVerdict::InvalidProof => light::CellVerdict::InvalidProof,
+            CellVerdict::Unavailable => light::CellVerdict::Unavailable,
+            CellVerdict::Malformed => light::CellVerdict::Malformed,
+            CellVerdict::Pending => light::CellVerdict::Pending,
+        };
+        Self {
+            row: cell.position.row,
+            col: cell.position.col.into(),
+            segment_index: cell.segment_index,
+            node: cell.node.unwrap_or_default(),
+            cached: cell.cached,
+            download_time_us: cell.download_time.as_micros() as u64,
+            verify_time_us: cell.verify_time.as_micros() as u64,
+            verdict: verdict.into(),
+        }
+    }
+}
+
+impl From<SampleReport> for SampleReply {
+    fn from(report: SampleReport) -> Self {
+        Self {
+            success: report.success(),
+            confidence: report.confidence,
+            anchored: report.anchored,
+            positions: report
+                .cells
+                .iter()
+                .map(|cell| CellPosition::from(cell.position))
+                .collect(),
+            cells: report.cells.into_iter().map(CellReport::from).collect(),
+        }
+    }
+}
+
+impl From<CellEvent> for SampleEvent {
+    fn from(event: CellEvent) -> Self {
+        Self {
+            event: Some(match event {
+                CellEvent::Downloaded(cell) => Event::Downloaded(cell.into()),
+                CellEvent::Verified(cell) => Event::Verified(cell.into()),
+            }),
+        }
+    }
+}
+
+impl From<store::SampleRecord> for SampleRecord {
+    fn from(record: store::SampleRecord) -> Self {
+        Self {
+            stream_id: record.stream_id.as_bytes().to_vec(),
+            batch_header_hash: record.batch_header_hash,
+            blob_index: record.blob_index,
+            timestamp_ms: record.timestamp_ms,
+            elapsed_us: record.elapsed_us,
+            success: record.success,
+            confidence: record.confidence,
+            anchored: record.anchored,
+            cells: record.cells.into_iter().map(CellReport::from).collect(),
+            error: record.error.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<store::CellRecord> for CellReport {
+    fn from(cell: store::CellRecord) -> Self {
+        let verdict = match cell.verdict {
+            Verdict::Verified => light::CellVerdict::Verified,
+            Verdict::InvalidProof => light::CellVerdict::InvalidProof,
+            Verdict::Unavailable => light::CellVerdict::Unavailable,
+            Verdict::Malformed => light::CellVerdict::Malformed,
+            Verdict::Pending => light::CellVerdict::Pending,
+        };
+        Self {
+            row: cell.row,
+            col: cell.col.into(),
+            segment_index: cell.segment_index,
+            node: cell.node.unwrap_or_default(),
+            cached: cell.cached,
+            download_time_us: cell.download_time_us,
+            verify_time_us: cell.verify_time_us,
+            verdict: verdict.into(),
+        }
     }
 }
 
@@ -29,31 +174,386 @@ impl Light for LightService {
         let remote_addr = request.remote_addr();
         let request_content = request.into_inner();
         info!(
-            "Received request from {:?}, blob_header_hash: {:x?}, blob_index: {:?}, times: {:?}",
+            "Received request from {:?}, blob_header_hash: {:x?}, blob_index: {:?}, times: {:?}, positions: {:?}, seed: {:x?}, confidence: {:?}",
             remote_addr,
             request_content.batch_header_hash,
             request_content.blob_index,
             request_content.times,
+            request_content.positions.len(),
+            request_content.seed,
+            request_content.confidence,
         );
+        let params = SampleParams::try_from(request_content)?;
         match self
             .sampler
-            .sample(
-                H256::from_slice(&request_content.stream_id),
-                request_content.batch_header_hash,
-                request_content.blob_index,
-                request_content.times,
+            .sample_report(
+                params.stream_id,
+                params.batch_header_hash,
+                params.blob_index,
+                params.positions,
             )
             .await
         {
-            Ok(result) => Ok(Response::new(SampleReply { success: result })),
-            Err(msg) => Err(Status::new(Code::Internal, msg.to_string())),
+            Ok(report) => Ok(Response::new(SampleReply::from(report))),
+            Err(e) => Err(error_status(e)),
+        }
+    }
+
+    type SampleStreamStream = ReceiverStream<Result<SampleEvent, Status>>;
+
+    async fn sample_stream(
+        &self,
+        request: Request<SampleRequest>,
+    ) -> Result<Response<Self::SampleStreamStream>, Status> {
+        let remote_addr = request.remote_addr();
+        let request_content = request.into_inner();
+        info!(
+            "Received stream request from {:?}, blob_header_hash: {:x?}, blob_index: {:?}, times: {:?}, positions: {:?}, confidence: {:?}",
+            remote_addr,
+            request_content.batch_header_hash,
+            request_content.blob_index,
+            request_content.times,
+            request_content.positions.len(),
+            request_content.confidence,
+        );
+        let params = SampleParams::try_from(request_content)?;
+        let sampler = self.sampler.clone();
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER_SIZE);
+        tokio::spawn(async move {
+            let (progress, mut events) = unbounded_channel();
+            let sample = sampler.sample_progress(
+                params.stream_id,
+                params.batch_header_hash,
+                params.blob_index,
+                params.positions,
+                Some(progress),
+            );
+            tokio::pin!(sample);
+            let result = loop {
+                tokio::select! {
+                    Some(event) = events.recv() => {
+                        // the client stopped listening, dropping the sample cancels it
+                        if sender.send(Ok(SampleEvent::from(event))).await.is_err() {
+                            return;
+                        }
+                    }
+                    result = &mut sample => break result,
+                    // the client disconnected while no event was sent, cancel the sample too
+                    _ = sender.closed() => return,
+                }
+            };
+            while let Ok(event) = events.try_recv() {
+                if sender.send(Ok(SampleEvent::from(event))).await.is_err() {
+                    return;
+                }
+            }
+            let last = match result {
+                Ok(report) => Ok(SampleEvent {
+                    event: Some(Event::Summary(SampleReply::from(report))),
+                }),
+                Err(e) => Err(error_status(e)),
+            };
+            let _ = sender.send(last).await;
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn sample_batch(
+        &self,
+        request: Request<SampleBatchRequest>,
+    ) -> Result<Response<SampleBatchReply>, Status> {
+        let remote_addr = request.remote_addr();
+        let request_content = request.into_inner();
+        info!(
+            "Received batch request from {:?}, blob_header_hash: {:x?}, times: {:?}, confidence: {:?}",
+            remote_addr,
+            request_content.batch_header_hash,
+            request_content.times,
+            request_content.confidence,
+        );
+        let params = SampleBatchParams::try_from(request_content)?;
+        match self
+            .sampler
+            .sample_batch(params.stream_id, params.batch_header_hash, params.positions)
+            .await
+        {
+            Ok(results) => {
+                let blobs: Vec<BlobSampleResult> = results
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, result)| match result {
+                        Ok(report) => BlobSampleResult {
+                            blob_index: i as u32,
+                            reply: Some(SampleReply::from(report)),
+                            ..Default::default()
+                        },
+                        Err(e) => {
+                            let (code, reason) = error_code(&e);
+                            BlobSampleResult {
+                                blob_index: i as u32,
+                                reply: None,
+                                error: e.to_string(),
+                                code: code as i32,
+                                reason: reason.to_string(),
+                            }
+                        }
+                    })
+                    .collect();
+                Ok(Response::new(SampleBatchReply {
+                    success: blobs
+                        .iter()
+                        .all(|blob| blob.reply.as_ref().is_some_and(|x| x.success)),
+                    blobs,
+                }))
+            }
+            Err(e) => Err(error_status(e)),
+        }
+    }
+
+    async fn list_samples_by_batch(
+        &self,
+        request: Request<ListSamplesByBatchRequest>,
+    ) -> Result<Response<ListSamplesReply>, Status> {
+        let params = ListByBatchParams::try_from(request.into_inner())?;
+        match self
+            .store()?
+            .by_batch(params.stream_id, &params.batch_header_hash, params.limit)
+        {
+            Ok(records) => Ok(Response::new(ListSamplesReply {
+                records: records.into_iter().map(SampleRecord::from).collect(),
+            })),
+            Err(e) => Err(error_status(e)),
+        }
+    }
+
+    async fn list_samples_by_time(
+        &self,
+        request: Request<ListSamplesByTimeRequest>,
+    ) -> Result<Response<ListSamplesReply>, Status> {
+        let params = ListByTimeParams::try_from(request.into_inner())?;
+        match self
+            .store()?
+            .by_time(params.start_ms, params.end_ms, params.limit)
+        {
+            Ok(records) => Ok(Response::new(ListSamplesReply {
+                records: records.into_iter().map(SampleRecord::from).collect(),
+            })),
+            Err(e) => Err(error_status(e)),
         }
     }
 
     async fn retrieve(
         &self,
-        _request: Request<RetrieveRequest>,
+        request: Request<RetrieveRequest>,
     ) -> Result<Response<RetrieveReply>, Status> {
-        todo!()
+        let remote_addr = request.remote_addr();
+        let request_content = request.into_inner();
+        info!(
+            "Received request from {:?}, blob_header_hash: {:x?}, blob_index: {:?}",
+            remote_addr, request_content.batch_header_hash, request_content.blob_index,
+        );
+        let params = RetrieveParams::try_from(request_content)?;
+        match self
+            .sampler
+            .retrieve(
+                params.stream_id,
+                params.batch_header_hash,
+                params.blob_index,
+            )
+            .await
+        {
+            Ok(blob) => Ok(Response::new(RetrieveReply {
+                status: true,
+                data: blob.data,
+                downloaded_rows: blob.downloaded_rows,
+                recovered_rows: blob.recovered_rows,
+                anchored: blob.anchored,
+            })),
+            Err(e) => Err(error_status(e)),
+        }
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsReply>, Status> {
+        let segment_cache = self.sampler.segment_cache_stats();
+        let batch_cache = self.sampler.batch_cache_stats();
+        let hedging = self.sampler.hedge_stats();
+        Ok(Response::new(GetStatsReply {
+            segment_cache: Some(SegmentCacheStats {
+                hits: segment_cache.hits,
+                misses: segment_cache.misses,
+                entries: segment_cache.entries as u64,
+                bytes: segment_cache.bytes as u64,
+            }),
+            batch_cache: Some(BatchCacheStats {
+                hits: batch_cache.hits,
+                not_found_hits: batch_cache.not_found_hits,
+                misses: batch_cache.misses,
+                entries: batch_cache.entries as u64,
+            }),
+            nodes: self
+                .sampler
+                .node_stats()
+                .into_iter()
+                .map(NodeStats::from)
+                .collect(),
+            hedging: Some(HedgeStats {
+                requests: hedging.requests,
+                hedged: hedging.hedged,
+                hedge_wins: hedging.hedge_wins,
+                budget_exhausted: hedging.budget_exhausted,
+                delay_us: hedging.delay.as_micros() as u64,
+            }),
+            coverage_gaps: self.sampler.coverage_gaps(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use prost::Message;
+    use tonic::Code;
+
+    use super::*;
+    use sampler::SamplerConfig;
+
+    use crate::validate::MAX_SAMPLE_CELLS;
+
+    fn service() -> LightService {
+        // nothing listens here, requests must be rejected before reaching the network
+        let url = "http://127.0.0.1:1".to_string();
+        LightService::new(
+            Arc::new(
+                Sampler::new(SamplerConfig {
+                    zgs_urls: vec![url.clone()],
+                    indexer_url: None,
+                    kv_url: url,
+                    compute_threads: 1,
+                    segment_cache_bytes: 0,
+                    download_timeout: None,
+                    batch_cache_entries: 0,
+                    batch_not_found_ttl: Duration::ZERO,
+                    allow_unanchored: false,
+                })
+                .unwrap(),
+            ),
+            None,
+        )
+    }
+
+    fn sample_request() -> SampleRequest {
+        SampleRequest {
+            stream_id: vec![1; 32],
+            batch_header_hash: vec![2; 32],
+            times: 10,
+            ..Default::default()
+        }
+    }
+
+    async fn sample_code(request: SampleRequest) -> Code {
+        service()
+            .sample(Request::new(request))
+            .await
+            .unwrap_err()
+            .code()
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_length_ids() {
+        for stream_id in [vec![], vec![1; 31], vec![1; 33]] {
+            let request = SampleRequest {
+                stream_id,
+                ..sample_request()
+            };
+            assert_eq!(sample_code(request).await, Code::InvalidArgument);
+        }
+        let request = SampleRequest {
+            batch_header_hash: vec![2; 5],
+            ..sample_request()
+        };
+        assert_eq!(sample_code(request).await, Code::InvalidArgument);
+
+        let request = RetrieveRequest {
+            stream_id: vec![1; 32],
+            batch_header_hash: vec![],
+            blob_index: 0,
+        };
+        let status = service().retrieve(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn rejects_sample_counts_out_of_range() {
+        for times in [0, MAX_SAMPLE_CELLS as u32 + 1, u32::MAX] {
+            let request = SampleRequest {
+                times,
+                ..sample_request()
+            };
+            assert_eq!(sample_code(request).await, Code::InvalidArgument);
+        }
+        let request = SampleRequest {
+            positions: vec![CellPosition::default(); MAX_SAMPLE_CELLS + 1],
+            ..sample_request()
+        };
+        assert_eq!(sample_code(request).await, Code::InvalidArgument);
+        // confidence against a tiny withholding ratio needs far more cells than allowed
+        let request = SampleRequest {
+            confidence: 0.999999,
+            withholding_ratio: 1e-9,
+            ..sample_request()
+        };
+        assert_eq!(sample_code(request).await, Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_sample_parameters() {
+        let requests = [
+            SampleRequest {
+                seed: vec![3; 16],
+                ..sample_request()
+            },
+            SampleRequest {
+                positions: vec![CellPosition {
+                    row: 0,
+                    col: u32::MAX,
+                }],
+                ..sample_request()
+            },
+            SampleRequest {
+                confidence: f64::NAN,
+                ..sample_request()
+            },
+            SampleRequest {
+                confidence: 1.0,
+                ..sample_request()
+            },
+            SampleRequest {
+                confidence: 0.9,
+                withholding_ratio: -0.5,
+                ..sample_request()
+            },
+        ];
+        for request in requests {
+            assert_eq!(sample_code(request).await, Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_decoded_malformed_messages() {
+        // stream_id field with a 3 byte payload, then an unknown field
+        let bytes = [0x0a, 0x03, 0x01, 0x02, 0x03, 0xf8, 0x01, 0x05];
+        let request = SampleRequest::decode(&bytes[..]).unwrap();
+        assert_eq!(sample_code(request).await, Code::InvalidArgument);
+
+        let request = RetrieveRequest::decode(&[][..]).unwrap();
+        let status = service().retrieve(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // length prefix beyond the end of the message
+        assert!(SampleRequest::decode(&[0x0a, 0x20, 0x01][..]).is_err());
     }
 }
@@ -0,0 +1,90 @@
+use std::{collections::HashMap, time::Duration};
+
+use sampler::SampleError;
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+const ERROR_DOMAIN: &str = "light.0g.ai";
+// suggested backoff for failures that may go away on their own
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Maps a sampler failure to a status, typed failures get a matching code and a
+/// `google.rpc.ErrorInfo` whose reason clients can branch on
+pub fn error_status(e: anyhow::Error) -> Status {
+    let Some(error) = e.chain().find_map(|x| x.downcast_ref::<SampleError>()) else {
+        return Status::new(Code::Internal, e.to_string());
+    };
+    let (code, reason, metadata) = classify(error);
+    let mut details = ErrorDetails::with_error_info(
+        reason,
+        ERROR_DOMAIN,
+        metadata
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect::<HashMap<_, _>>(),
+    );
+    if matches!(code, Code::Unavailable | Code::DeadlineExceeded) {
+        details.set_retry_info(Some(RETRY_DELAY));
+    }
+    Status::with_error_details(code, e.to_string(), details)
+}
+
+/// Code and `ErrorInfo` reason `error_status` gives a failure, the reason is empty for
+/// untyped failures
+pub fn error_code(e: &anyhow::Error) -> (Code, &'static str) {
+    match e.chain().find_map(|x| x.downcast_ref::<SampleError>()) {
+        Some(error) => {
+            let (code, reason, _) = classify(error);
+            (code, reason)
+        }
+        None => (Code::Internal, ""),
+    }
+}
+
+fn classify(error: &SampleError) -> (Code, &'static str, Vec<(&'static str, String)>) {
+    match error {
+        SampleError::BatchNotFound => (Code::NotFound, "BATCH_NOT_FOUND", vec![]),
+        SampleError::InvalidBlobIndex { index, blobs } => (
+            Code::InvalidArgument,
+            "INVALID_BLOB_INDEX",
+            vec![
+                ("blob_index", index.to_string()),
+                ("blobs", blobs.to_string()),
+            ],
+        ),
+        SampleError::InvalidDimensions { rows, cols } => (
+            Code::FailedPrecondition,
+            "INVALID_DIMENSIONS",
+            vec![("rows", rows.to_string()), ("cols", cols.to_string())],
+        ),
+        SampleError::InvalidArgument(_) => (Code::InvalidArgument, "INVALID_ARGUMENT", vec![]),
+        SampleError::SegmentUnavailable {
+            segment_index,
+            data_root,
+        } => (
+            Code::Unavailable,
+            "SEGMENT_UNAVAILABLE",
+            vec![
+                ("segment_index", segment_index.to_string()),
+                ("data_root", format!("{:?}", data_root)),
+            ],
+        ),
+        SampleError::FileNotFinalized { data_root } => (
+            Code::Unavailable,
+            "FILE_NOT_FINALIZED",
+            vec![("data_root", format!("{:?}", data_root))],
+        ),
+        SampleError::NotRecoverable { available, rows } => (
+            Code::Unavailable,
+            "NOT_RECOVERABLE",
+            vec![
+                ("available", available.to_string()),
+                ("rows", rows.to_string()),
+            ],
+        ),
+        SampleError::ProofInvalid(_) => (Code::DataLoss, "PROOF_INVALID", vec![]),
+        SampleError::UpstreamTimeout(_) => (Code::DeadlineExceeded, "UPSTREAM_TIMEOUT", vec![]),
+        SampleError::UpstreamUnavailable(_) => (Code::Unavailable, "UPSTREAM_UNAVAILABLE", vec![]),
+        SampleError::MalformedSegment(_) => (Code::DataLoss, "MALFORMED_SEGMENT", vec![]),
+    }
+}
@@ -0,0 +1,259 @@
+use ethereum_types::H256;
+use sampler::{max_cells_for_confidence, Position, SamplePositions, DEFAULT_WITHHOLDING_RATIO};
+use tonic::{Code, Status};
+
+use crate::service::light::{
+    CellPosition, ListSamplesByBatchRequest, ListSamplesByTimeRequest, RetrieveRequest,
+    SampleBatchRequest, SampleRequest,
+};
+
+// upper limit of cells sampled by one request, by times or by explicit positions
+pub const MAX_SAMPLE_CELLS: usize = 4096;
+// upper limit of records listed by one query
+pub const MAX_LIST_LIMIT: usize = 1000;
+const HASH_SIZE: usize = 32;
+const SEED_SIZE: usize = 32;
+
+fn invalid_argument(message: impl Into<String>) -> Status {
+    Status::new(Code::InvalidArgument, message)
+}
+
+fn parse_hash(name: &str, bytes: &[u8]) -> Result<H256, Status> {
+    if bytes.len() != HASH_SIZE {
+        return Err(invalid_argument(format!(
+            "{} must be {} bytes, got {}",
+            name,
+            HASH_SIZE,
+            bytes.len()
+        )));
+    }
+    Ok(H256::from_slice(bytes))
+}
+
+/// A sample request with all fields checked
+#[derive(Debug)]
+pub struct SampleParams {
+    pub stream_id: H256,
+    pub batch_header_hash: Vec<u8>,
+    pub blob_index: u32,
+    pub positions: SamplePositions,
+}
+
+impl TryFrom<SampleRequest> for SampleParams {
+    type Error = Status;
+
+    fn try_from(request: SampleRequest) -> Result<Self, Self::Error> {
+        let stream_id = parse_hash("stream_id", &request.stream_id)?;
+        parse_hash("batch_header_hash", &request.batch_header_hash)?;
+        let positions = SamplePositions::try_from(&request)?;
+        Ok(Self {
+            stream_id,
+            batch_header_hash: request.batch_header_hash,
+            blob_index: request.blob_index,
+            positions,
+        })
+    }
+}
+
+/// A retrieve request with all fields checked
+#[derive(Debug)]
+pub struct RetrieveParams {
+    pub stream_id: H256,
+    pub batch_header_hash: Vec<u8>,
+    pub blob_index: u32,
+}
+
+impl TryFrom<RetrieveRequest> for RetrieveParams {
+    type Error = Status;
+
+    fn try_from(request: RetrieveRequest) -> Result<Self, Self::Error> {
+        let stream_id = parse_hash("stream_id", &request.stream_id)?;
+        parse_hash("batch_header_hash", &request.batch_header_hash)?;
+        Ok(Self {
+            stream_id,
+            batch_header_hash: request.batch_header_hash,
+            blob_index: request.blob_index,
+        })
+    }
+}
+
+fn list_limit(limit: u32) -> Result<usize, Status> {
+    match limit as usize {
+        0 => Ok(MAX_LIST_LIMIT),
+        limit if limit > MAX_LIST_LIMIT => Err(invalid_argument(format!(
+            "limit must be at most {}, got {}",
+            MAX_LIST_LIMIT, limit
+        ))),
+        limit => Ok(limit),
+    }
+}
+
+/// A query of the samples of a batch with all fields checked
+#[derive(Debug)]
+pub struct ListByBatchParams {
+    pub stream_id: H256,
+    pub batch_header_hash: Vec<u8>,
+    pub limit: usize,
+}
+
+impl TryFrom<ListSamplesByBatchRequest> for ListByBatchParams {
+    type Error = Status;
+
+    fn try_from(request: ListSamplesByBatchRequest) -> Result<Self, Self::Error> {
+        let stream_id = parse_hash("stream_id", &request.stream_id)?;
+        parse_hash("batch_header_hash", &request.batch_header_hash)?;
+        Ok(Self {
+            stream_id,
+            batch_header_hash: request.batch_header_hash,
+            limit: list_limit(request.limit)?,
+        })
+    }
+}
+
+/// A query of the samples in a time range with all fields checked
+#[derive(Debug)]
+pub struct ListByTimeParams {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub limit: usize,
+}
+
+impl TryFrom<ListSamplesByTimeRequest> for ListByTimeParams {
+    type Error = Status;
+
+    fn try_from(request: ListSamplesByTimeRequest) -> Result<Self, Self::Error> {
+        if request.start_ms >= request.end_ms {
+            return Err(invalid_argument(format!(
+                "start_ms {} must be before end_ms {}",
+                request.start_ms, request.end_ms
+            )));
+        }
+        Ok(Self {
+            start_ms: request.start_ms,
+            end_ms: request.end_ms,
+            limit: list_limit(request.limit)?,
+        })
+    }
+}
+
+/// A batch sample request with all fields checked
+#[derive(Debug)]
+pub struct SampleBatchParams {
+    pub stream_id: H256,
+    pub batch_header_hash: Vec<u8>,
+    pub positions: SamplePositions,
+}
+
+impl TryFrom<SampleBatchRequest> for SampleBatchParams {
+    type Error = Status;
+
+    fn try_from(request: SampleBatchRequest) -> Result<Self, Self::Error> {
+        let stream_id = parse_hash("stream_id", &request.stream_id)?;
+        parse_hash("batch_header_hash", &request.batch_header_hash)?;
+        let positions = sample_positions(
+            &[],
+            request.times,
+            &request.seed,
+            request.confidence,
+            request.withholding_ratio,
+        )?;
+        Ok(Self {
+            stream_id,
+            batch_header_hash: request.batch_header_hash,
+            positions,
+        })
+    }
+}
+
+impl TryFrom<&SampleRequest> for SamplePositions {
+    type Error = Status;
+
+    fn try_from(request: &SampleRequest) -> Result<Self, Self::Error> {
+        sample_positions(
+            &request.positions,
+            request.times,
+            &request.seed,
+            request.confidence,
+            request.withholding_ratio,
+        )
+    }
+}
+
+/// Checks the sample fields shared by the sample requests, explicit positions take precedence
+/// over confidence, which takes precedence over times
+fn sample_positions(
+    positions: &[CellPosition],
+    times: u32,
+    seed: &[u8],
+    confidence: f64,
+    withholding_ratio: f64,
+) -> Result<SamplePositions, Status> {
+    if !positions.is_empty() {
+        if positions.len() > MAX_SAMPLE_CELLS {
+            return Err(invalid_argument(format!(
+                "at most {} positions can be sampled, got {}",
+                MAX_SAMPLE_CELLS,
+                positions.len()
+            )));
+        }
+        return positions
+            .iter()
+            .map(|x| {
+                Ok(Position {
+                    row: x.row,
+                    col: x
+                        .col
+                        .try_into()
+                        .map_err(|_| invalid_argument("column out of range"))?,
+                })
+            })
+            .collect::<Result<Vec<_>, Status>>()
+            .map(SamplePositions::Explicit);
+    }
+    let seed: Option<[u8; SEED_SIZE]> = if seed.is_empty() {
+        None
+    } else {
+        Some(
+            seed.try_into()
+                .map_err(|_| invalid_argument(format!("seed must be {} bytes", SEED_SIZE)))?,
+        )
+    };
+    if !confidence.is_finite() || !(0.0..1.0).contains(&confidence) {
+        return Err(invalid_argument("confidence must be in range [0, 1)"));
+    }
+    if !withholding_ratio.is_finite() || !(0.0..=1.0).contains(&withholding_ratio) {
+        return Err(invalid_argument(
+            "withholding_ratio must be in range (0, 1]",
+        ));
+    }
+    if confidence > 0.0 {
+        let withholding_ratio = if withholding_ratio > 0.0 {
+            withholding_ratio
+        } else {
+            DEFAULT_WITHHOLDING_RATIO
+        };
+        // checked before the matrix is known, against the count no matrix can exceed
+        let cells = max_cells_for_confidence(confidence, withholding_ratio);
+        if cells > MAX_SAMPLE_CELLS as f64 {
+            return Err(invalid_argument(format!(
+                "confidence {} against withholding_ratio {} needs up to {} cells, at most {} can be sampled",
+                confidence, withholding_ratio, cells, MAX_SAMPLE_CELLS
+            )));
+        }
+        return Ok(SamplePositions::Confidence {
+            confidence,
+            withholding_ratio,
+            seed,
+        });
+    }
+    if times == 0 || times as usize > MAX_SAMPLE_CELLS {
+        return Err(invalid_argument(format!(
+            "times must be in range [1, {}], got {}",
+            MAX_SAMPLE_CELLS, times
+        )));
+    }
+    Ok(match seed {
+        Some(seed) => SamplePositions::Seeded { seed, times },
+        None => SamplePositions::Random(times),
+    })
+}
@@ -1,12 +1,14 @@
 #[macro_use]
 extern crate tracing;
 
-use std::{error::Error, net::SocketAddr, str::FromStr};
+use std::{error::Error, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, bail, Result};
 use config::Config;
+use ethereum_types::H256;
 use grpc::run_server;
-use sampler::Sampler;
+use sampler::{Sampler, SamplerConfig, Watcher, WatcherConfig};
+use store::SampleStore;
 use tokio::signal;
 use tracing::Level;
 
@@ -53,20 +55,102 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // sampler
 
-    let sampler = Sampler::new(
-        node_config
+    let mut sampler = Sampler::new(SamplerConfig {
+        zgs_urls: node_config
             .settings
             .get_array("zgs_urls")?
             .iter()
             .map(|x| x.to_string())
             .collect(),
-        &node_config.settings.get_string("kv_url")?,
-    )?;
+        indexer_url: node_config.settings.get_string("indexer_url").ok(),
+        kv_url: node_config.settings.get_string("kv_url")?,
+        compute_threads: node_config.settings.get_int("compute_threads").unwrap_or(0) as usize,
+        segment_cache_bytes: node_config
+            .settings
+            .get_int("segment_cache_bytes")
+            .unwrap_or(0) as usize,
+        download_timeout: match node_config
+            .settings
+            .get_int("download_timeout_secs")
+            .unwrap_or(0)
+        {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        },
+        batch_cache_entries: node_config
+            .settings
+            .get_int("batch_cache_entries")
+            .unwrap_or(0) as usize,
+        batch_not_found_ttl: Duration::from_secs(
+            node_config
+                .settings
+                .get_int("batch_not_found_ttl_secs")
+                .unwrap_or(0) as u64,
+        ),
+        allow_unanchored: node_config
+            .settings
+            .get_bool("allow_unanchored_commitments")
+            .unwrap_or(false),
+    })?;
+
+    // store
+    let store = match node_config.settings.get_string("store_path") {
+        Ok(path) => {
+            info!("opening sample store at {:?}", path);
+            let retention = match node_config
+                .settings
+                .get_int("store_retention_secs")
+                .unwrap_or(0)
+            {
+                0 => None,
+                secs => Some(Duration::from_secs(secs as u64)),
+            };
+            let store = Arc::new(SampleStore::open(path, retention)?);
+            sampler.set_recorder(store.clone());
+            Some(store)
+        }
+        Err(_) => None,
+    };
+    let sampler = Arc::new(sampler);
+
+    // watcher
+    let stream_ids = node_config
+        .settings
+        .get_array("watcher.stream_ids")
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| H256::from_str(&x.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if !stream_ids.is_empty() {
+        let confidence = node_config.settings.get_float("watcher.confidence")?;
+        if !(0.0..1.0).contains(&confidence) {
+            return Err(
+                format!("watcher.confidence must be in [0, 1), got {:?}", confidence).into(),
+            );
+        }
+        let poll_interval_secs = node_config.settings.get_int("watcher.poll_interval_secs")?;
+        if poll_interval_secs <= 0 {
+            return Err(format!(
+                "watcher.poll_interval_secs must be positive, got {:?}",
+                poll_interval_secs
+            )
+            .into());
+        }
+        let watcher = Watcher::new(
+            sampler.clone(),
+            WatcherConfig {
+                stream_ids,
+                confidence,
+                poll_interval: Duration::from_secs(poll_interval_secs as u64),
+            },
+        );
+        tokio::spawn(watcher.run());
+    }
 
     // start server
     let server_addr = node_config.settings.get_string("grpc_listen_address")?;
     info!("starting grpc server at {:?}", server_addr);
-    run_server(SocketAddr::from_str(&server_addr).unwrap(), sampler).await?;
+    run_server(SocketAddr::from_str(&server_addr).unwrap(), sampler, store).await?;
 
     tokio::select! {
         _ = signal::ctrl_c() => {},
@@ -0,0 +1,153 @@
+use anyhow::{bail, Result};
+use common::{error::SampleError, merkle::commitment_root, types::KVBatchInfo, COMMITMENT_SIZE};
+use ethereum_types::H256;
+
+pub type Commitment = [u8; COMMITMENT_SIZE as usize];
+
+/// Checks the row commitments listed in the batch info against the batch root and returns
+/// them by blob. Rows are verified against these commitments only, the commitment stored next
+/// to a row in its segment comes from the storage node and is not trusted.
+///
+/// A batch whose blobs list no commitments fails as an invalid proof, unless
+/// `allow_unanchored` is set: `None` is returned then and the rows can only be checked against
+/// the commitments served with them.
+pub fn anchored_commitments(
+    batch_info: &KVBatchInfo,
+    allow_unanchored: bool,
+) -> Result<Option<Vec<Vec<Commitment>>>> {
+    if batch_info
+        .blob_disperse_infos
+        .iter()
+        .all(|blob| blob.commitments.is_empty())
+    {
+        if !allow_unanchored {
+            bail!(SampleError::ProofInvalid(format!(
+                "batch of data root {:?} lists no row commitments",
+                batch_info.batch_header.data_root
+            )));
+        }
+        warn!(
+            "Batch of data root {:?} lists no row commitments, rows are not anchored",
+            batch_info.batch_header.data_root
+        );
+        return Ok(None);
+    }
+
+    let blobs = batch_info
+        .blob_disperse_infos
+        .iter()
+        .enumerate()
+        .map(|(i, blob)| {
+            if blob.commitments.len() != blob.rows as usize {
+                bail!(SampleError::ProofInvalid(format!(
+                    "blob {:?} lists {:?} commitments for {:?} rows",
+                    i,
+                    blob.commitments.len(),
+                    blob.rows
+                )));
+            }
+            blob.commitments
+                .iter()
+                .map(|x| {
+                    Commitment::try_from(x.as_slice()).map_err(|_| {
+                        SampleError::ProofInvalid(format!("invalid commitment size {:?}", x.len()))
+                            .into()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let batch_root = &batch_info.batch_header.batch_root;
+    let root = commitment_root(blobs.iter().flatten().map(|x| x.as_slice()));
+    if batch_root.len() != H256::len_bytes() || root.as_bytes() != batch_root.as_slice() {
+        bail!(SampleError::ProofInvalid(format!(
+            "row commitments are not anchored, computed root {:?}, batch root {:?}",
+            root, batch_root
+        )));
+    }
+    Ok(Some(blobs))
+}
+
+#[cfg(test)]
+mod tests {
+    use common::types::{BatchHeader, BlobDisperseInfo};
+
+    use super::*;
+
+    fn batch(blobs: Vec<Vec<Vec<u8>>>, batch_root: Vec<u8>) -> KVBatchInfo {
+        KVBatchInfo {
+            batch_header: BatchHeader {
+                batch_root,
+                data_root: H256::zero(),
+            },
+            blob_disperse_infos: blobs
+                .into_iter()
+                .map(|commitments| BlobDisperseInfo {
+                    blob_length: 0,
+                    rows: commitments.len() as u32,
+                    cols: 1,
+                    commitments,
+                })
+                .collect(),
+        }
+    }
+
+    fn commitments(rows: u8) -> Vec<Vec<u8>> {
+        (0..rows)
+            .map(|i| vec![i; COMMITMENT_SIZE as usize])
+            .collect()
+    }
+
+    fn root_of(blobs: &[Vec<Vec<u8>>]) -> Vec<u8> {
+        commitment_root(blobs.iter().flatten().map(|x| x.as_slice()))
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn commitments_matching_batch_root_are_anchored() {
+        let blobs = vec![commitments(2), commitments(3)];
+        let root = root_of(&blobs);
+
+        let anchored = anchored_commitments(&batch(blobs, root), false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(anchored.len(), 2);
+        assert_eq!(anchored[1].len(), 3);
+        assert_eq!(anchored[1][2], [2; COMMITMENT_SIZE as usize]);
+    }
+
+    #[test]
+    fn batch_without_commitments_is_rejected_unless_allowed() {
+        let info = batch(vec![vec![], vec![]], vec![7; 32]);
+        assert!(anchored_commitments(&info, false).is_err());
+        assert!(anchored_commitments(&info, true).unwrap().is_none());
+    }
+
+    #[test]
+    fn mismatched_commitments_are_rejected() {
+        let blobs = vec![commitments(2), commitments(3)];
+        let root = root_of(&blobs);
+        let mut tampered = blobs.clone();
+        tampered[1][0][0] ^= 1;
+        assert!(anchored_commitments(&batch(tampered, root.clone()), true).is_err());
+
+        // a blob missing its list while another lists them is not a valid batch
+        let mut partial = batch(blobs.clone(), root.clone());
+        partial.blob_disperse_infos[0].commitments.clear();
+        assert!(anchored_commitments(&partial, true).is_err());
+
+        let mut short = batch(blobs, root);
+        short.blob_disperse_infos[1].commitments[2].pop();
+        assert!(anchored_commitments(&short, true).is_err());
+    }
+
+    #[test]
+    fn malformed_batch_root_is_rejected() {
+        let blobs = vec![commitments(2)];
+        let mut root = root_of(&blobs);
+        root.push(0);
+        assert!(anchored_commitments(&batch(blobs, root), true).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Result};
+use common::error::SampleError;
+use ethereum_types::H256;
+use kate_recovery::matrix::Dimensions;
+
+use crate::{
+    anchor::anchored_commitments,
+    group::{group_by_row, RowGroup},
+    positions::sample_confidence,
+    record::{not_finalized, SampleRun},
+    report::{CellReport, SampleReport},
+    SamplePositions, Sampler,
+};
+
+/// Sampled cells of one blob of a batch
+struct BlobSample {
+    dimensions: Dimensions,
+    groups: Vec<RowGroup>,
+    cells: Vec<CellReport>,
+}
+
+impl Sampler {
+    /// Samples every blob of a batch with the same `positions` spec. The batch info and the
+    /// layout are fetched and computed once, and a segment holding rows of several blobs is
+    /// downloaded once. Returns the outcome of each blob in blob order, a blob that cannot be
+    /// sampled gets an error without failing the others.
+    pub async fn sample_batch(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        positions: SamplePositions,
+    ) -> Result<Vec<Result<SampleReport>>> {
+        let started_at = SystemTime::now();
+        let timer = std::time::Instant::now();
+        let result = self
+            .sample_blobs(stream_id, batch_header_hash.clone(), positions)
+            .await;
+        let elapsed = timer.elapsed();
+        match &result {
+            Ok(blobs) => {
+                for (i, blob) in blobs.iter().enumerate() {
+                    self.record(SampleRun {
+                        stream_id,
+                        batch_header_hash: &batch_header_hash,
+                        blob_index: Some(i as u32),
+                        started_at,
+                        elapsed,
+                        result: blob,
+                    });
+                }
+            }
+            Err(e) if not_finalized(e) => {}
+            Err(e) => self.record(SampleRun {
+                stream_id,
+                batch_header_hash: &batch_header_hash,
+                blob_index: None,
+                started_at,
+                elapsed,
+                result: &Err(anyhow!(e.to_string())),
+            }),
+        }
+        result
+    }
+
+    async fn sample_blobs(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        positions: SamplePositions,
+    ) -> Result<Vec<Result<SampleReport>>> {
+        let batch = self.fetch_batch(stream_id, batch_header_hash).await?;
+        let batch_info = &batch.info;
+        let mut timer = std::time::Instant::now();
+
+        let commitments = anchored_commitments(batch_info, self.allow_unanchored)?;
+        let data_root = batch_info.batch_header.data_root;
+        let withholding_ratio = positions.withholding_ratio();
+        let mut blobs: Vec<Result<BlobSample>> = batch_info
+            .blob_disperse_infos
+            .iter()
+            .zip(batch.locations.iter())
+            .map(|(blob_info, location)| {
+                let Some(dimensions) =
+                    Dimensions::new(blob_info.rows as u16, blob_info.cols as u16)
+                else {
+                    bail!(SampleError::InvalidDimensions {
+                        rows: blob_info.rows,
+                        cols: blob_info.cols,
+                    });
+                };
+                let positions = positions.clone().resolve(dimensions)?;
+                Ok(BlobSample {
+                    dimensions,
+                    groups: group_by_row(location, &positions),
+                    cells: positions
+                        .iter()
+                        .map(|position| {
+                            CellReport::unavailable(
+                                *position,
+                                location.segment_indexes[position.row as usize],
+                            )
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+
+        let segment_indexes: Vec<usize> = blobs
+            .iter()
+            .flatten()
+            .flat_map(|blob| blob.groups.iter())
+            .map(|group| group.segment_index as usize)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let segments: HashMap<_, _> = segment_indexes
+            .iter()
+            .cloned()
+            .zip(
+                self.zgs
+                    .try_download_segments(data_root, segment_indexes.clone())
+                    .await?,
+            )
+            .filter_map(|(index, segment)| Some((index, segment?)))
+            .collect();
+        info!(
+            "download {:?} segments for {:?} blobs used {:?}ms",
+            segment_indexes.len(),
+            blobs.len(),
+            timer.elapsed().as_millis()
+        );
+        timer = std::time::Instant::now();
+
+        for (i, blob) in blobs.iter_mut().enumerate() {
+            let Ok(blob) = blob else {
+                continue;
+            };
+            self.verify_groups(
+                blob.dimensions,
+                commitments.as_ref().map(|x| x[i].as_slice()),
+                &blob.groups,
+                &segments,
+                &mut blob.cells,
+            )
+            .await?;
+        }
+        info!(
+            "verify {:?} blobs used {:?}ms",
+            blobs.len(),
+            timer.elapsed().as_millis()
+        );
+
+        Ok(blobs
+            .into_iter()
+            .map(|blob| {
+                let blob = blob?;
+                Ok(SampleReport {
+                    confidence: sample_confidence(
+                        blob.dimensions,
+                        blob.cells.len() as u32,
+                        withholding_ratio,
+                    ),
+                    cells: blob.cells,
+                    anchored: commitments.is_some(),
+                })
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use common::types::{BlobLocation, KVBatchInfo};
+use ethereum_types::H256;
+use lru::LruCache;
+
+/// Batch info together with the location of the rows of each blob
+pub struct BatchLayout {
+    pub info: KVBatchInfo,
+    pub locations: Vec<BlobLocation>,
+}
+
+/// Hit and miss counts of the batch cache, hits on batches known to be missing are counted
+/// apart
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchCacheStats {
+    pub hits: u64,
+    pub not_found_hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+enum Entry {
+    Found(Arc<BatchLayout>),
+    // batches may land later, so missing ones are only remembered for a while
+    NotFound(Instant),
+}
+
+type BatchKey = (H256, Vec<u8>);
+
+/// LRU cache of batch layouts keyed by stream id and batch header hash. Batch info is
+/// immutable once written, so found batches stay until evicted.
+pub struct BatchCache {
+    entries: Option<Mutex<LruCache<BatchKey, Entry>>>,
+    not_found_ttl: Duration,
+    hits: AtomicU64,
+    not_found_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BatchCache {
+    /// A cache of at most `capacity` batches, 0 disables the cache
+    pub fn new(capacity: usize, not_found_ttl: Duration) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity).map(|x| Mutex::new(LruCache::new(x))),
+            not_found_ttl,
+            hits: AtomicU64::new(0),
+            not_found_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns `Some(None)` if the batch was recently not found
+    pub fn get(&self, key: &BatchKey) -> Option<Option<Arc<BatchLayout>>> {
+        let Some(entries) = &self.entries else {
+            return None;
+        };
+        let mut entries = entries.lock().expect("lock poisoned");
+        let cached = match entries.get(key) {
+            Some(Entry::Found(layout)) => Some(Some(layout.clone())),
+            Some(Entry::NotFound(at)) if at.elapsed() < self.not_found_ttl => Some(None),
+            _ => None,
+        };
+        if cached.is_none() {
+            // drops an expired not found entry
+            entries.pop(key);
+        }
+        match cached {
+            Some(Some(_)) => self.hits.fetch_add(1, Ordering::Relaxed),
+            Some(None) => self.not_found_hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        cached
+    }
+
+    pub fn insert(&self, key: BatchKey, layout: Option<Arc<BatchLayout>>) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+        let entry = match layout {
+            Some(layout) => Entry::Found(layout),
+            None => Entry::NotFound(Instant::now()),
+        };
+        entries.lock().expect("lock poisoned").put(key, entry);
+    }
+
+    pub fn stats(&self) -> BatchCacheStats {
+        BatchCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            not_found_hits: self.not_found_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self
+                .entries
+                .as_ref()
+                .map(|x| x.lock().expect("lock poisoned").len())
+                .unwrap_or(0),
+        }
+    }
+}
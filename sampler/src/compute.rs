@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tokio::sync::oneshot;
+
+/// Thread pool for the CPU-bound work of the sampler (interpolation, proving, pairings and
+/// reconstruction), so that it does not stall the async runtime serving requests
+#[derive(Clone)]
+pub struct ComputePool {
+    pool: Arc<ThreadPool>,
+}
+
+impl ComputePool {
+    /// Creates a pool of `threads` threads, one per cpu if `threads` is 0
+    pub fn new(threads: usize) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("sampler-compute-{}", i))
+            .panic_handler(|e| error!("compute task panicked: {:?}", e))
+            .build()?;
+        Ok(Self {
+            pool: Arc::new(pool),
+        })
+    }
+
+    /// Runs `f` on the pool, parallel iterators inside `f` are scheduled on the pool as well
+    pub async fn run<F, T>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.pool.spawn(move || {
+            // the receiver is gone if the request was dropped
+            let _ = sender.send(f());
+        });
+        receiver.await.map_err(|_| anyhow!("compute task aborted"))
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use common::types::BlobLocation;
+use kate_recovery::matrix::Position;
+
+/// Sampled cells sharing a row, the row is downloaded and interpolated once for all of them
+#[derive(Clone, Debug)]
+pub struct RowGroup {
+    pub row: u32,
+    pub segment_index: u32,
+    pub offset: u32,
+    /// indexes of the cells in the sampled positions
+    pub cells: Vec<usize>,
+}
+
+/// Groups sampled positions by row, ordered by segment and offset
+pub fn group_by_row(location: &BlobLocation, positions: &[Position]) -> Vec<RowGroup> {
+    let mut groups: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (i, position) in positions.iter().enumerate() {
+        groups.entry(position.row).or_default().push(i);
+    }
+    let mut groups: Vec<RowGroup> = groups
+        .into_iter()
+        .map(|(row, cells)| RowGroup {
+            row,
+            segment_index: location.segment_indexes[row as usize],
+            offset: location.offsets[row as usize],
+            cells,
+        })
+        .collect();
+    groups.sort_by_key(|x| (x.segment_index, x.offset));
+    groups
+}
+
+/// Distinct segments holding the grouped rows, in ascending order
+pub fn segment_indexes(groups: &[RowGroup]) -> Vec<usize> {
+    let mut indexes: Vec<usize> = groups.iter().map(|x| x.segment_index as usize).collect();
+    indexes.dedup();
+    indexes
+}
@@ -1,63 +1,150 @@
 #[macro_use]
 extern crate tracing;
 
-use std::{collections::HashSet, error::Error, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{anyhow, bail, Result};
-use common::{allocate_rows, types::BlobLocation, COMMITMENT_SIZE};
-use data_fetcher::{kv_fetcher::fetch_kv_batch_info, zgs_fetcher::download_segments};
+use batch_cache::{BatchCache, BatchLayout};
+use common::{allocate_rows, types::BlobLocation};
+use compute::ComputePool;
+use data_fetcher::{
+    kv_fetcher::fetch_kv_batch_info,
+    single_flight::{Flight, SingleFlight},
+    zgs_fetcher::{DownloadedSegment, ZgsFetcher},
+};
 use ethereum_types::H256;
+use group::{group_by_row, segment_indexes, RowGroup};
 use jsonrpsee::http_client::HttpClient;
-use kate::gridgen::{AsBytes, EvaluationGrid};
-use kate_recovery::{
-    data::Cell,
-    matrix::{Dimensions, Position},
-    proof,
-};
+use kate_recovery::matrix::Dimensions;
 use kv_rpc::build_client;
-use rand::{thread_rng, Rng};
+use record::not_finalized;
+use row::split_row;
+use tokio::sync::mpsc::UnboundedSender;
+use verify::{Params, SampledRow, Verifier};
+
+mod anchor;
+mod batch;
+mod batch_cache;
+mod compute;
+mod group;
+mod positions;
+mod record;
+mod recovery;
+mod report;
+mod retrieve;
+mod row;
+mod verify;
+mod watcher;
+
+pub use anchor::{anchored_commitments, Commitment};
+pub use batch_cache::BatchCacheStats;
+pub use common::error::SampleError;
+pub use data_fetcher::{
+    hedging::HedgeStats,
+    node_health::{CircuitState, NodeStats},
+    segment_cache::CacheStats,
+};
+pub use kate_recovery::matrix::Position;
+pub use positions::{
+    cells_for_confidence, generate_random_cells, generate_seeded_cells, max_cells_for_confidence,
+    sample_confidence, SamplePositions, DEFAULT_WITHHOLDING_RATIO,
+};
+pub use record::{SampleRecorder, SampleRun};
+pub use recovery::recover_rows;
+pub use report::{CellEvent, CellReport, CellVerdict, SampleReport};
+pub use retrieve::RetrievedBlob;
+pub use watcher::{Watcher, WatcherConfig};
+
+pub struct SamplerConfig {
+    pub zgs_urls: Vec<String>,
+    /// indexer asked for more storage nodes holding a file, only `zgs_urls` are used if unset
+    pub indexer_url: Option<String>,
+    pub kv_url: String,
+    /// threads of the pool used for verification and recovery, 0 means one thread per cpu
+    pub compute_threads: usize,
+    /// bytes of validated segments kept in memory, 0 disables the segment cache
+    pub segment_cache_bytes: usize,
+    /// time allowed to download the segments of one request, unlimited if `None`
+    pub download_timeout: Option<Duration>,
+    /// batches whose info and layout are kept in memory, 0 disables the batch cache
+    pub batch_cache_entries: usize,
+    /// how long a batch that was not found is reported missing without asking kv again
+    pub batch_not_found_ttl: Duration,
+    /// sample and retrieve batches that list no row commitments, checking rows against the
+    /// commitments served by the storage nodes, such runs are reported as not anchored
+    pub allow_unanchored: bool,
+}
 
 pub struct Sampler {
-    zgs_clients: Vec<HttpClient>,
+    zgs: ZgsFetcher,
     // kv settings
     kv_client: HttpClient,
-}
-
-/// Generates random cell positions for sampling
-pub fn generate_random_cells(dimensions: Dimensions, cell_count: u32) -> Vec<Position> {
-    let max_cells = dimensions.size();
-    let count = if max_cells < cell_count {
-        debug!("Max cells count {max_cells} is lesser than cell_count {cell_count}");
-        max_cells
-    } else {
-        cell_count
-    };
-    let mut rng = thread_rng();
-    let mut indices = HashSet::new();
-    while (indices.len() as u16) < count as u16 {
-        let col = rng.gen_range(0..dimensions.cols().into());
-        let row = rng.gen_range(0..dimensions.rows().into());
-        indices.insert(Position {
-            row: row.into(),
-            col,
-        });
-    }
-
-    indices.into_iter().collect::<Vec<_>>()
+    // pool for the cpu-bound verification and recovery
+    compute: ComputePool,
+    batch_cache: BatchCache,
+    // batch info fetches in flight, shared by concurrent requests
+    batch_flights: SingleFlight<(H256, Vec<u8>), Option<Arc<BatchLayout>>>,
+    recorder: Option<Arc<dyn SampleRecorder>>,
+    allow_unanchored: bool,
+    // commitment scheme parameters, loaded once
+    params: Arc<Params>,
 }
 
 impl Sampler {
-    pub fn new(zgs_urls: Vec<String>, kv_url: &String) -> Result<Self> {
+    pub fn new(config: SamplerConfig) -> Result<Self> {
         Ok(Self {
-            zgs_clients: zgs_urls
-                .iter()
-                .map(build_client)
-                .collect::<Result<Vec<HttpClient>, Box<dyn Error>>>()
-                .map_err(|e| anyhow!(e.to_string()))?,
-            kv_client: build_client(kv_url).map_err(|e| anyhow!(e.to_string()))?,
+            zgs: ZgsFetcher::new(
+                &config.zgs_urls,
+                config.segment_cache_bytes,
+                config.indexer_url.as_deref(),
+                config.download_timeout,
+            )?,
+            kv_client: build_client(&config.kv_url).map_err(|e| anyhow!(e.to_string()))?,
+            compute: ComputePool::new(config.compute_threads)?,
+            batch_cache: BatchCache::new(config.batch_cache_entries, config.batch_not_found_ttl),
+            batch_flights: SingleFlight::new(),
+            recorder: None,
+            allow_unanchored: config.allow_unanchored,
+            params: Arc::new(Params::load()),
         })
     }
 
+    pub fn segment_cache_stats(&self) -> CacheStats {
+        self.zgs.cache_stats()
+    }
+
+    pub fn hedge_stats(&self) -> HedgeStats {
+        self.zgs.hedge_stats()
+    }
+
+    /// Segments that no storage node's shard covers
+    pub fn coverage_gaps(&self) -> u64 {
+        self.zgs.coverage_gaps()
+    }
+
+    /// Health of each storage node, in configuration order
+    pub fn node_stats(&self) -> Vec<NodeStats> {
+        self.zgs.node_stats()
+    }
+
+    /// Records every sampling run with `recorder`
+    pub fn set_recorder(&mut self, recorder: Arc<dyn SampleRecorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    fn record(&self, run: SampleRun) {
+        if run.result.as_ref().is_err_and(not_finalized) {
+            return;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(run);
+        }
+    }
+
     pub async fn sample(
         &self,
         stream_id: H256,
@@ -65,142 +152,330 @@ impl Sampler {
         blob_index: u32,
         times: u32,
     ) -> Result<bool> {
-        let mut timer = std::time::Instant::now();
-        if let Some(batch_info) =
-            fetch_kv_batch_info(self.kv_client.clone(), stream_id, batch_header_hash).await?
-        {
-            info!(
-                "fetch kv batch info used {:?}ms",
-                timer.elapsed().as_millis()
-            );
-            timer = std::time::Instant::now();
-
-            if batch_info.blob_disperse_infos.len() <= blob_index as usize {
-                bail!(anyhow!("invalid blob index"));
-            }
+        Ok(self
+            .sample_report(
+                stream_id,
+                batch_header_hash,
+                blob_index,
+                SamplePositions::Random(times),
+            )
+            .await?
+            .success())
+    }
 
-            let rows = batch_info.blob_disperse_infos[blob_index as usize].rows;
-            let cols = batch_info.blob_disperse_infos[blob_index as usize].cols;
-            let Some(dimensions) = Dimensions::new(rows as u16, cols as u16) else {
-                info!(
-                    "Skipping block with invalid dimensions {:?}x{:?}",
-                    rows, cols
-                );
-                return Ok(false);
-            };
-            let data_root = batch_info.batch_header.data_root;
-            let blob_locations = allocate_rows(&batch_info.blob_disperse_infos);
-            let positions = generate_random_cells(dimensions, times);
-
-            info!(
-                "generate sample positions used {:?}ms, matrix {:?}x{:?}",
-                timer.elapsed().as_millis(),
-                rows,
-                cols
-            );
-
-            match self
-                .verify_cells(
-                    dimensions,
-                    &blob_locations[blob_index as usize],
-                    data_root,
-                    positions,
-                )
-                .await
-            {
-                Ok(success) => Ok(success),
-                Err(e) => {
-                    debug!("sample failed with error {:?}", e.to_string());
-                    Ok(false)
+    /// Samples cells of a blob and reports the outcome of every sampled cell
+    pub async fn sample_report(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        blob_index: u32,
+        positions: SamplePositions,
+    ) -> Result<SampleReport> {
+        self.sample_progress(stream_id, batch_header_hash, blob_index, positions, None)
+            .await
+    }
+
+    /// Same as [`Sampler::sample_report`], sending a [`CellEvent`] to `progress` as each cell
+    /// is downloaded and verified
+    pub async fn sample_progress(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        blob_index: u32,
+        positions: SamplePositions,
+        progress: Option<UnboundedSender<CellEvent>>,
+    ) -> Result<SampleReport> {
+        let started_at = SystemTime::now();
+        let timer = std::time::Instant::now();
+        let result = self
+            .sample_blob(
+                stream_id,
+                batch_header_hash.clone(),
+                blob_index,
+                positions,
+                progress,
+            )
+            .await;
+        self.record(SampleRun {
+            stream_id,
+            batch_header_hash: &batch_header_hash,
+            blob_index: Some(blob_index),
+            started_at,
+            elapsed: timer.elapsed(),
+            result: &result,
+        });
+        result
+    }
+
+    async fn sample_blob(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        blob_index: u32,
+        positions: SamplePositions,
+        progress: Option<UnboundedSender<CellEvent>>,
+    ) -> Result<SampleReport> {
+        let batch = self.fetch_batch(stream_id, batch_header_hash).await?;
+        let batch_info = &batch.info;
+        let timer = std::time::Instant::now();
+
+        if batch_info.blob_disperse_infos.len() <= blob_index as usize {
+            bail!(SampleError::InvalidBlobIndex {
+                index: blob_index,
+                blobs: batch_info.blob_disperse_infos.len(),
+            });
+        }
+
+        let rows = batch_info.blob_disperse_infos[blob_index as usize].rows;
+        let cols = batch_info.blob_disperse_infos[blob_index as usize].cols;
+        let Some(dimensions) = Dimensions::new(rows as u16, cols as u16) else {
+            bail!(SampleError::InvalidDimensions { rows, cols });
+        };
+        let commitments = anchored_commitments(batch_info, self.allow_unanchored)?;
+        let data_root = batch_info.batch_header.data_root;
+        let withholding_ratio = positions.withholding_ratio();
+        let positions = positions.resolve(dimensions)?;
+
+        info!(
+            "generate sample positions used {:?}ms, matrix {:?}x{:?}",
+            timer.elapsed().as_millis(),
+            rows,
+            cols
+        );
+
+        let mut report = self
+            .report_cells(
+                dimensions,
+                &batch.locations[blob_index as usize],
+                commitments
+                    .as_ref()
+                    .map(|x| x[blob_index as usize].as_slice()),
+                data_root,
+                positions,
+                progress.as_ref(),
+            )
+            .await?;
+        report.confidence =
+            sample_confidence(dimensions, report.cells.len() as u32, withholding_ratio);
+        Ok(report)
+    }
+
+    /// Fetches the batch info and computes the blob layout, or serves them from the cache.
+    /// Concurrent requests for the same batch share one fetch.
+    async fn fetch_batch(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+    ) -> Result<Arc<BatchLayout>> {
+        let key = (stream_id, batch_header_hash);
+        loop {
+            if let Some(cached) = self.batch_cache.get(&key) {
+                return cached.ok_or_else(|| SampleError::BatchNotFound.into());
+            }
+            match self.batch_flights.join(key.clone()) {
+                Flight::Leader(leader) => {
+                    let timer = std::time::Instant::now();
+                    // on error the leader is dropped and the waiting requests fetch by themselves
+                    let batch =
+                        fetch_kv_batch_info(self.kv_client.clone(), stream_id, key.1.clone())
+                            .await?
+                            .map(|info| {
+                                Arc::new(BatchLayout {
+                                    locations: allocate_rows(&info.blob_disperse_infos),
+                                    info,
+                                })
+                            });
+                    info!(
+                        "fetch kv batch info used {:?}ms",
+                        timer.elapsed().as_millis()
+                    );
+                    self.batch_cache.insert(key, batch.clone());
+                    leader.complete(batch.clone());
+                    return batch.ok_or_else(|| SampleError::BatchNotFound.into());
+                }
+                Flight::Follower(follower) => {
+                    if let Some(batch) = follower.wait().await {
+                        return batch.ok_or_else(|| SampleError::BatchNotFound.into());
+                    }
                 }
             }
-        } else {
-            bail!(anyhow!("batch not found"));
         }
     }
 
+    pub fn batch_cache_stats(&self) -> BatchCacheStats {
+        self.batch_cache.stats()
+    }
+
     pub async fn verify_cells(
         &self,
         dimensions: Dimensions,
         location: &BlobLocation,
+        commitments: Option<&[Commitment]>,
         data_root: H256,
         positions: Vec<Position>,
     ) -> Result<bool> {
+        Ok(self
+            .report_cells(
+                dimensions,
+                location,
+                commitments,
+                data_root,
+                positions,
+                None,
+            )
+            .await?
+            .success())
+    }
+
+    /// Downloads and verifies the given cells, a cell that cannot be downloaded or verified
+    /// is reported with its verdict instead of failing the whole sample. Cells are grouped by
+    /// row so that every segment is downloaded once and every row is interpolated once.
+    /// Rows are verified against the anchored `commitments` of the blob, by row, or against the
+    /// commitments served with them if the batch lists none.
+    pub async fn report_cells(
+        &self,
+        dimensions: Dimensions,
+        location: &BlobLocation,
+        commitments: Option<&[Commitment]>,
+        data_root: H256,
+        positions: Vec<Position>,
+        progress: Option<&UnboundedSender<CellEvent>>,
+    ) -> Result<SampleReport> {
         let mut timer = std::time::Instant::now();
 
-        let segment_indexes = positions
+        if let Some(commitments) = commitments {
+            if commitments.len() != location.segment_indexes.len() {
+                bail!(anyhow!(
+                    "{:?} commitments for {:?} rows",
+                    commitments.len(),
+                    location.segment_indexes.len()
+                ));
+            }
+        }
+        let groups = group_by_row(location, &positions);
+        let segment_indexes = segment_indexes(&groups);
+        let mut cells: Vec<CellReport> = positions
             .iter()
-            .map(|x| location.segment_indexes[x.row as usize] as usize)
+            .map(|position| {
+                CellReport::unavailable(*position, location.segment_indexes[position.row as usize])
+            })
             .collect();
-        let offsets: Vec<usize> = positions
+        let segments: HashMap<usize, DownloadedSegment> = segment_indexes
             .iter()
-            .map(|x| location.offsets[x.row as usize] as usize)
-            .collect();
-        let row_byte_size = dimensions.row_byte_size();
-        let segments =
-            download_segments(self.zgs_clients.clone(), data_root, segment_indexes).await?;
-
-        info!("download segments used {:?}ms", timer.elapsed().as_millis());
-
-        let cols = u16::from(dimensions.cols()) as usize;
-        let pp = Arc::new(kate_recovery::couscous::public_params());
-        for ((segment, offset), position) in
-            segments.iter().zip(offsets.iter()).zip(positions.iter())
-        {
-            timer = std::time::Instant::now();
-            // generate 1-row matrix
-            let evals = EvaluationGrid::from_row_slices(
-                1,
-                cols,
-                segment[*offset..*offset + row_byte_size].to_vec(),
+            .cloned()
+            .zip(
+                self.zgs
+                    .try_download_segments_with(data_root, segment_indexes.clone(), |i, segment| {
+                        let Some(progress) = progress else {
+                            return;
+                        };
+                        let downloaded = groups
+                            .iter()
+                            .filter(|x| x.segment_index as usize == segment_indexes[i])
+                            .flat_map(|x| x.cells.iter());
+                        for cell in downloaded {
+                            let mut report = cells[*cell].clone();
+                            if let Some(segment) = segment {
+                                report.node = Some(segment.node.clone());
+                                report.cached = segment.cached;
+                                report.download_time = segment.elapsed;
+                                report.verdict = CellVerdict::Pending;
+                            }
+                            // the receiver is gone if the caller stopped listening
+                            let _ = progress.send(CellEvent::Downloaded(report));
+                        }
+                    })
+                    .await?,
             )
-            .map_err(|e| anyhow!(format!("Grid construction failed: {:?}", e)))?;
-            // make polynomial
-            let polys = evals
-                .make_polynomial_grid()
-                .map_err(|e| anyhow!(format!("Make polynomial grid failed: {:?}", e)))?;
+            .filter_map(|(index, segment)| Some((index, segment?)))
+            .collect();
 
-            let Some(data) = evals.get::<usize, usize>(0, position.col as usize) else {
-                bail!(anyhow!(
-                    "Invalid position {:?} for dims {:?}",
-                    position,
-                    evals.dims()
-                ));
-            };
-            let proof = match polys.proof(
-                &kate::couscous::multiproof_params(),
-                &kate::com::Cell {
-                    row: zerog_core::BlockLengthRows(0),
-                    col: zerog_core::BlockLengthColumns(position.col.into()),
-                },
-            ) {
-                Ok(x) => x,
-                Err(e) => bail!(anyhow!("Unable to make proof: {:?}", e)),
-            };
+        info!(
+            "download {:?} segments for {:?} rows used {:?}ms",
+            segment_indexes.len(),
+            groups.len(),
+            timer.elapsed().as_millis()
+        );
+        timer = std::time::Instant::now();
 
-            let data = data.to_bytes().expect("Ser cannot fail").to_vec();
-            let proof = proof.to_bytes().expect("Ser cannot fail").to_vec();
-            let content = [proof, data].into_iter().flatten().collect::<Vec<_>>();
-            let cell = Cell {
-                position: Position {
-                    row: 0,
-                    col: position.col,
-                },
-                content: content.as_slice().try_into()?,
-            };
-            let commitment: &[u8; 48] = segment
-                [*offset + row_byte_size..*offset + row_byte_size + COMMITMENT_SIZE as usize]
-                .try_into()?;
-            info!("generate proof used {:?}ms", timer.elapsed().as_millis());
-            timer = std::time::Instant::now();
+        self.verify_groups(dimensions, commitments, &groups, &segments, &mut cells)
+            .await?;
 
-            let verification_success = proof::verify(&pp, dimensions, commitment, &cell)?;
-            info!("verification used {:?}ms", timer.elapsed().as_millis());
+        if let Some(progress) = progress {
+            for cell in cells.iter() {
+                let _ = progress.send(CellEvent::Verified(cell.clone()));
+            }
+        }
+
+        info!(
+            "verify {:?} cells used {:?}ms",
+            cells.len(),
+            timer.elapsed().as_millis()
+        );
+        Ok(SampleReport {
+            cells,
+            confidence: 0.0,
+            anchored: commitments.is_some(),
+        })
+    }
+
+    /// Verifies the cells of the row groups whose segments were downloaded, `cells` holds the
+    /// reports of the sampled positions the groups refer to
+    async fn verify_groups(
+        &self,
+        dimensions: Dimensions,
+        commitments: Option<&[Commitment]>,
+        groups: &[RowGroup],
+        segments: &HashMap<usize, DownloadedSegment>,
+        cells: &mut [CellReport],
+    ) -> Result<()> {
+        let row_byte_size = dimensions.row_byte_size();
+        let mut sampled_groups = vec![];
+        let mut sampled_rows = vec![];
+        for group in groups.iter() {
+            let Some(segment) = segments.get(&(group.segment_index as usize)) else {
+                continue;
+            };
+            for i in group.cells.iter() {
+                cells[*i].node = Some(segment.node.clone());
+                cells[*i].cached = segment.cached;
+                cells[*i].download_time = segment.elapsed;
+                cells[*i].verdict = CellVerdict::Malformed;
+            }
+            match split_row(&segment.data, group.offset as usize, row_byte_size) {
+                Ok((row, served)) => {
+                    let commitment = match commitments {
+                        Some(commitments) => {
+                            let anchored = commitments[group.row as usize];
+                            if *served != anchored {
+                                debug!("row {:?} served with an unanchored commitment", group.row);
+                            }
+                            anchored
+                        }
+                        None => *served,
+                    };
+                    sampled_rows.push(SampledRow {
+                        data: row.to_vec(),
+                        commitment,
+                        cols: group.cells.iter().map(|i| cells[*i].position.col).collect(),
+                    });
+                    sampled_groups.push(group);
+                }
+                Err(e) => debug!("row {:?} malformed: {:?}", group.row, e.to_string()),
+            }
+        }
 
-            if !verification_success {
-                return Ok(false);
+        let params = self.params.clone();
+        let verdicts = self
+            .compute
+            .run(move || Verifier::new(&params, dimensions).verify(&sampled_rows))
+            .await?;
+        for (group, verdicts) in sampled_groups.into_iter().zip(verdicts) {
+            for (i, (verdict, verify_time)) in group.cells.iter().zip(verdicts) {
+                cells[*i].verdict = verdict;
+                cells[*i].verify_time = verify_time;
             }
         }
-        Ok(true)
+        Ok(())
     }
 }
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use common::{error::SampleError, EXTENSION_FACTOR};
+use kate_recovery::matrix::{Dimensions, Position};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// Share of the cells that has to be withheld to make a matrix extended at rate
+/// `1 / EXTENSION_FACTOR` unrecoverable
+pub const DEFAULT_WITHHOLDING_RATIO: f64 = 1.0 - 1.0 / EXTENSION_FACTOR as f64;
+
+/// How the cells of a sample are chosen
+#[derive(Clone, Debug)]
+pub enum SamplePositions {
+    /// `times` random cells picked by the light node
+    Random(u32),
+    /// `times` cells derived from a client seed, see [`generate_seeded_cells`]
+    Seeded { seed: [u8; 32], times: u32 },
+    /// cells chosen by the client
+    Explicit(Vec<Position>),
+    /// as many cells as needed to detect withholding of `withholding_ratio` of the cells with
+    /// probability `confidence`, see [`cells_for_confidence`]
+    Confidence {
+        confidence: f64,
+        withholding_ratio: f64,
+        seed: Option<[u8; 32]>,
+    },
+}
+
+impl SamplePositions {
+    /// Returns the cells to sample in a blob with the given dimensions
+    pub fn resolve(self, dimensions: Dimensions) -> Result<Vec<Position>> {
+        match self {
+            Self::Random(times) => Ok(generate_random_cells(dimensions, times)),
+            Self::Seeded { seed, times } => Ok(generate_seeded_cells(dimensions, times, seed)),
+            Self::Explicit(positions) => {
+                let rows = u16::from(dimensions.rows()) as u32;
+                let cols = u16::from(dimensions.cols());
+                if let Some(position) = positions.iter().find(|x| x.row >= rows || x.col >= cols) {
+                    bail!(SampleError::InvalidArgument(format!(
+                        "position {:?} out of matrix {:?}x{:?}",
+                        position, rows, cols
+                    )));
+                }
+                Ok(positions)
+            }
+            Self::Confidence {
+                confidence,
+                withholding_ratio,
+                seed,
+            } => {
+                if !(0.0..1.0).contains(&confidence) {
+                    bail!(SampleError::InvalidArgument(format!(
+                        "confidence {:?} out of range [0, 1)",
+                        confidence
+                    )));
+                }
+                if withholding_ratio <= 0.0 || withholding_ratio > 1.0 {
+                    bail!(SampleError::InvalidArgument(format!(
+                        "withholding ratio {:?} out of range (0, 1]",
+                        withholding_ratio
+                    )));
+                }
+                let times = cells_for_confidence(dimensions, confidence, withholding_ratio);
+                Ok(match seed {
+                    Some(seed) => generate_seeded_cells(dimensions, times, seed),
+                    None => generate_random_cells(dimensions, times),
+                })
+            }
+        }
+    }
+
+    /// Withholding ratio the confidence of the sample is reported against
+    pub fn withholding_ratio(&self) -> f64 {
+        match self {
+            Self::Confidence {
+                withholding_ratio, ..
+            } => *withholding_ratio,
+            _ => DEFAULT_WITHHOLDING_RATIO,
+        }
+    }
+}
+
+/// Number of distinct cells to sample so that withholding `withholding_ratio` of the cells is
+/// detected with probability at least `confidence`, capped at the number of cells
+pub fn cells_for_confidence(
+    dimensions: Dimensions,
+    confidence: f64,
+    withholding_ratio: f64,
+) -> u32 {
+    let mut miss = MissProbability::new(dimensions, withholding_ratio);
+    let mut count = 0;
+    while count < dimensions.size() && 1.0 - miss.value < confidence {
+        miss.sample_one();
+        count += 1;
+    }
+    count
+}
+
+/// Number of cells to sample to detect withholding of `withholding_ratio` of the cells with
+/// probability `confidence` if cells were drawn with replacement. Distinct cells detect
+/// withholding faster, so this bounds [`cells_for_confidence`] for every matrix.
+pub fn max_cells_for_confidence(confidence: f64, withholding_ratio: f64) -> f64 {
+    ((1.0 - confidence).ln() / (1.0 - withholding_ratio).ln()).ceil()
+}
+
+/// Probability that sampling `cell_count` distinct cells detects withholding of
+/// `withholding_ratio` of the cells
+pub fn sample_confidence(dimensions: Dimensions, cell_count: u32, withholding_ratio: f64) -> f64 {
+    let mut miss = MissProbability::new(dimensions, withholding_ratio);
+    for _ in 0..cell_count.min(dimensions.size()) {
+        miss.sample_one();
+    }
+    1.0 - miss.value
+}
+
+/// Probability that none of the sampled cells is withheld, cells are drawn without replacement
+struct MissProbability {
+    total: f64,
+    available: f64,
+    value: f64,
+}
+
+impl MissProbability {
+    fn new(dimensions: Dimensions, withholding_ratio: f64) -> Self {
+        let total = dimensions.size() as f64;
+        Self {
+            total,
+            available: total - (total * withholding_ratio).ceil().min(total),
+            value: 1.0,
+        }
+    }
+
+    fn sample_one(&mut self) {
+        self.value *= (self.available / self.total).max(0.0);
+        self.available -= 1.0;
+        self.total -= 1.0;
+    }
+}
+
+/// Generates random cell positions for sampling
+pub fn generate_random_cells(dimensions: Dimensions, cell_count: u32) -> Vec<Position> {
+    generate_cells(dimensions, cell_count, &mut thread_rng())
+}
+
+/// Generates cell positions from a ChaCha20 stream seeded with `seed`, the same seed always
+/// yields the same positions in the same order
+pub fn generate_seeded_cells(
+    dimensions: Dimensions,
+    cell_count: u32,
+    seed: [u8; 32],
+) -> Vec<Position> {
+    generate_cells(dimensions, cell_count, &mut ChaCha20Rng::from_seed(seed))
+}
+
+fn generate_cells<R: Rng>(dimensions: Dimensions, cell_count: u32, rng: &mut R) -> Vec<Position> {
+    let max_cells = dimensions.size();
+    let count = if max_cells < cell_count {
+        debug!("Max cells count {max_cells} is lesser than cell_count {cell_count}");
+        max_cells
+    } else {
+        cell_count
+    };
+    let mut indices = HashSet::new();
+    let mut positions = Vec::with_capacity(count as usize);
+    while positions.len() < count as usize {
+        let col = rng.gen_range(0..dimensions.cols().into());
+        let row = rng.gen_range(0..dimensions.rows().into());
+        let position = Position {
+            row: row.into(),
+            col,
+        };
+        if indices.insert(position) {
+            positions.push(position);
+        }
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dimensions(rows: u16, cols: u16) -> Dimensions {
+        Dimensions::new(rows, cols).unwrap()
+    }
+
+    #[test]
+    fn cells_for_confidence_matches_closed_form() {
+        // detecting 50% withholding with 99.9999% needs ceil(log2(1e6)) = 20 cells drawn with
+        // replacement, a large matrix needs the same number of distinct cells
+        let large = dimensions(256, 256);
+        assert_eq!(max_cells_for_confidence(0.999999, 0.5), 20.0);
+        assert_eq!(cells_for_confidence(large, 0.999999, 0.5), 20);
+        assert!(sample_confidence(large, 20, 0.5) >= 0.999999);
+        assert!(sample_confidence(large, 19, 0.5) < 0.999999);
+    }
+
+    #[test]
+    fn cells_for_confidence_is_capped_by_the_matrix() {
+        let small = dimensions(4, 4);
+        assert!(cells_for_confidence(small, 0.999999, 1e-9) <= small.size());
+        assert_eq!(sample_confidence(small, small.size(), 1e-9), 1.0);
+    }
+
+    #[test]
+    fn max_cells_for_confidence_bounds_cells_for_confidence() {
+        for (rows, cols) in [(4, 4), (16, 32), (256, 256), (1024, 64)] {
+            for confidence in [0.5, 0.9, 0.99, 0.999999] {
+                for withholding_ratio in [0.01, 0.1, 0.25, 0.5, 0.75] {
+                    let cells =
+                        cells_for_confidence(dimensions(rows, cols), confidence, withholding_ratio);
+                    assert!(
+                        cells as f64 <= max_cells_for_confidence(confidence, withholding_ratio),
+                        "{}x{}, confidence {}, withholding ratio {}",
+                        rows,
+                        cols,
+                        confidence,
+                        withholding_ratio
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_cells_are_reproducible_distinct_and_in_bounds() {
+        let dimensions = dimensions(16, 32);
+        let positions = generate_seeded_cells(dimensions, 100, [7; 32]);
+        assert_eq!(positions, generate_seeded_cells(dimensions, 100, [7; 32]));
+        assert_ne!(positions, generate_seeded_cells(dimensions, 100, [8; 32]));
+
+        assert_eq!(positions.len(), 100);
+        assert_eq!(positions.iter().collect::<HashSet<_>>().len(), 100);
+        assert!(positions
+            .iter()
+            .all(|x| x.row < 16 && x.col < u16::from(dimensions.cols())));
+
+        // asking for more cells than the matrix has yields every cell once
+        let all = generate_seeded_cells(dimensions, 1000, [7; 32]);
+        assert_eq!(all.len(), dimensions.size() as usize);
+        assert_eq!(all.iter().collect::<HashSet<_>>().len(), all.len());
+    }
+}
@@ -0,0 +1,32 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use common::error::SampleError;
+use ethereum_types::H256;
+
+use crate::report::SampleReport;
+
+/// One sampling run of a blob, or of a whole batch if `blob_index` is `None` and the batch
+/// could not be sampled at all
+pub struct SampleRun<'a> {
+    pub stream_id: H256,
+    pub batch_header_hash: &'a [u8],
+    pub blob_index: Option<u32>,
+    pub started_at: SystemTime,
+    pub elapsed: Duration,
+    pub result: &'a Result<SampleReport>,
+}
+
+/// Receives every sampling run of a [`crate::Sampler`], except runs of files that are not
+/// finalized yet, which are retried rather than failed. Recording must not block.
+pub trait SampleRecorder: Send + Sync {
+    fn record(&self, run: SampleRun);
+}
+
+/// Whether the run failed because the file of the batch is still being stored
+pub(crate) fn not_finalized(e: &anyhow::Error) -> bool {
+    matches!(
+        e.chain().find_map(|x| x.downcast_ref::<SampleError>()),
+        Some(SampleError::FileNotFinalized { .. })
+    )
+}
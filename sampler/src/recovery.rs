@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use common::{error::SampleError, COEFF_SIZE, EXTENSION_FACTOR};
+use dusk_bytes::Serializable;
+use kate_recovery::{
+    com::reconstruct_column,
+    data::DataCell,
+    matrix::{Dimensions, Position},
+};
+use rayon::prelude::*;
+
+/// Rebuilds the `missing` rows of the extended matrix from the `available` rows, columns are
+/// reconstructed in parallel and at least `1 / EXTENSION_FACTOR` of the rows must be available
+pub fn recover_rows(
+    dimensions: Dimensions,
+    available: &HashMap<usize, Vec<u8>>,
+    missing: &[usize],
+) -> Result<HashMap<usize, Vec<u8>>> {
+    let rows = u16::from(dimensions.rows()) as usize;
+    if available.len() * (EXTENSION_FACTOR as usize) < rows {
+        bail!(SampleError::NotRecoverable {
+            available: available.len(),
+            rows,
+        });
+    }
+
+    let cols = u16::from(dimensions.cols()) as usize;
+    let coeff_size = COEFF_SIZE as usize;
+    // recovered coefficients of the missing rows, by column
+    let columns = (0..cols)
+        .into_par_iter()
+        .map(|col| -> Result<Vec<[u8; COEFF_SIZE as usize]>> {
+            let cells = available
+                .iter()
+                .map(|(row, data)| DataCell {
+                    position: Position {
+                        row: *row as u32,
+                        col: col as u16,
+                    },
+                    data: data[col * coeff_size..(col + 1) * coeff_size]
+                        .try_into()
+                        .expect("coefficient size is fixed"),
+                })
+                .collect::<Vec<_>>();
+            let column = reconstruct_column(dimensions.rows(), &cells)
+                .map_err(|e| anyhow!(format!("Column {:?} reconstruction failed: {:?}", col, e)))?;
+            Ok(missing.iter().map(|row| column[*row].to_bytes()).collect())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(missing
+        .iter()
+        .enumerate()
+        .map(|(i, row)| (*row, columns.iter().flat_map(|column| column[i]).collect()))
+        .collect())
+}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use kate_recovery::matrix::Position;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellVerdict {
+    /// the cell proof verified against the row commitment
+    Verified,
+    /// the cell proof did not verify against the row commitment
+    InvalidProof,
+    /// no storage node served the segment holding the cell
+    Unavailable,
+    /// the segment was served but the row or commitment could not be decoded
+    Malformed,
+    /// the segment was downloaded and the cell is waiting for verification
+    Pending,
+}
+
+/// Outcome of sampling a single cell
+#[derive(Clone, Debug)]
+pub struct CellReport {
+    pub position: Position,
+    pub segment_index: u32,
+    /// url of the storage node that served the segment
+    pub node: Option<String>,
+    /// the segment was served from the cache, `node` served it first and `download_time` is
+    /// zero
+    pub cached: bool,
+    pub download_time: Duration,
+    pub verify_time: Duration,
+    pub verdict: CellVerdict,
+}
+
+impl CellReport {
+    /// Report of a cell whose segment has not been downloaded
+    pub fn unavailable(position: Position, segment_index: u32) -> Self {
+        Self {
+            position,
+            segment_index,
+            node: None,
+            cached: false,
+            download_time: Duration::ZERO,
+            verify_time: Duration::ZERO,
+            verdict: CellVerdict::Unavailable,
+        }
+    }
+}
+
+/// Progress of a sample, every cell is reported once its segment is downloaded or given up and
+/// once more with its final verdict
+#[derive(Clone, Debug)]
+pub enum CellEvent {
+    Downloaded(CellReport),
+    Verified(CellReport),
+}
+
+/// Outcome of sampling a blob
+#[derive(Clone, Debug, Default)]
+pub struct SampleReport {
+    pub cells: Vec<CellReport>,
+    /// probability that the sampled cells detect the withholding ratio the sample targeted
+    pub confidence: f64,
+    /// cells were verified against commitments anchored by the batch root, not the ones
+    /// served by the storage nodes
+    pub anchored: bool,
+}
+
+impl SampleReport {
+    pub fn success(&self) -> bool {
+        self.cells
+            .iter()
+            .all(|cell| cell.verdict == CellVerdict::Verified)
+    }
+}
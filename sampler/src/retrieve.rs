@@ -0,0 +1,204 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{anyhow, bail, Result};
+use common::{error::SampleError, types::BlobLocation, EXTENSION_FACTOR};
+use ethereum_types::H256;
+use kate_recovery::matrix::Dimensions;
+use rayon::prelude::*;
+
+use crate::{
+    anchor::{anchored_commitments, Commitment},
+    recovery::recover_rows,
+    row::{row_commitment, row_data, split_row},
+    Sampler,
+};
+
+/// Blob bytes together with how each original row was obtained
+#[derive(Debug)]
+pub struct RetrievedBlob {
+    pub data: Vec<u8>,
+    /// original rows downloaded and verified against their commitments
+    pub downloaded_rows: Vec<u32>,
+    /// original rows rebuilt from the extended matrix
+    pub recovered_rows: Vec<u32>,
+    /// rows were checked against commitments anchored by the batch root, not the served ones
+    pub anchored: bool,
+}
+
+impl Sampler {
+    /// Downloads the original rows of a blob and returns the blob bytes, rows that are
+    /// unavailable or fail verification are rebuilt from the extended rows
+    pub async fn retrieve(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        blob_index: u32,
+    ) -> Result<RetrievedBlob> {
+        let batch = self.fetch_batch(stream_id, batch_header_hash).await?;
+        let batch_info = &batch.info;
+        let mut timer = std::time::Instant::now();
+
+        if batch_info.blob_disperse_infos.len() <= blob_index as usize {
+            bail!(SampleError::InvalidBlobIndex {
+                index: blob_index,
+                blobs: batch_info.blob_disperse_infos.len(),
+            });
+        }
+
+        let blob_info = &batch_info.blob_disperse_infos[blob_index as usize];
+        let Some(dimensions) = Dimensions::new(blob_info.rows as u16, blob_info.cols as u16) else {
+            bail!(SampleError::InvalidDimensions {
+                rows: blob_info.rows,
+                cols: blob_info.cols,
+            });
+        };
+        let commitments = anchored_commitments(batch_info, self.allow_unanchored)?
+            .map(|mut x| x.swap_remove(blob_index as usize));
+        let data_root = batch_info.batch_header.data_root;
+        let location = &batch.locations[blob_index as usize];
+
+        // only the original rows are needed to rebuild the blob
+        let (original_rows, extended_rows): (Vec<usize>, Vec<usize>) =
+            (0..blob_info.rows as usize).partition(|row| row % EXTENSION_FACTOR as usize == 0);
+        let mut rows = self
+            .download_rows(
+                dimensions,
+                location,
+                commitments.as_deref(),
+                data_root,
+                &original_rows,
+            )
+            .await?;
+        let missing_rows: Vec<usize> = original_rows
+            .iter()
+            .filter(|row| !rows.contains_key(row))
+            .cloned()
+            .collect();
+
+        info!(
+            "download original rows used {:?}ms, matrix {:?}x{:?}, missing rows {:?}",
+            timer.elapsed().as_millis(),
+            blob_info.rows,
+            blob_info.cols,
+            missing_rows.len()
+        );
+
+        if !missing_rows.is_empty() {
+            timer = std::time::Instant::now();
+            rows.extend(
+                self.download_rows(
+                    dimensions,
+                    location,
+                    commitments.as_deref(),
+                    data_root,
+                    &extended_rows,
+                )
+                .await?,
+            );
+            let missing = missing_rows.clone();
+            let (available, recovered) = self
+                .compute
+                .run(move || {
+                    let recovered = recover_rows(dimensions, &rows, &missing);
+                    (rows, recovered)
+                })
+                .await?;
+            rows = available;
+            rows.extend(recovered?);
+            info!("recover rows used {:?}ms", timer.elapsed().as_millis());
+        }
+
+        let mut data = Vec::with_capacity(blob_info.blob_length as usize);
+        for row in original_rows.iter() {
+            data.extend(row_data(&rows[row]));
+        }
+        if (data.len() as u64) < blob_info.blob_length {
+            bail!(anyhow!(
+                "blob length {:?} exceeds matrix capacity {:?}",
+                blob_info.blob_length,
+                data.len()
+            ));
+        }
+        data.truncate(blob_info.blob_length as usize);
+
+        Ok(RetrievedBlob {
+            data,
+            downloaded_rows: original_rows
+                .iter()
+                .filter(|row| !missing_rows.contains(row))
+                .map(|row| *row as u32)
+                .collect(),
+            recovered_rows: missing_rows.iter().map(|row| *row as u32).collect(),
+            anchored: commitments.is_some(),
+        })
+    }
+
+    /// Downloads the given rows of a blob and checks each of them against its anchored
+    /// commitment, or the served one if the batch lists none, on the compute pool, rows that
+    /// are unavailable or fail verification are left out
+    async fn download_rows(
+        &self,
+        dimensions: Dimensions,
+        location: &BlobLocation,
+        commitments: Option<&[Commitment]>,
+        data_root: H256,
+        rows: &[usize],
+    ) -> Result<HashMap<usize, Vec<u8>>> {
+        let segment_indexes: Vec<usize> = rows
+            .iter()
+            .map(|row| location.segment_indexes[*row] as usize)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let segments: HashMap<usize, Vec<u8>> = segment_indexes
+            .iter()
+            .cloned()
+            .zip(
+                self.zgs
+                    .try_download_segments(data_root, segment_indexes)
+                    .await?,
+            )
+            .filter_map(|(index, segment)| Some((index, segment?.data)))
+            .collect();
+
+        let cols = u16::from(dimensions.cols()) as usize;
+        let row_byte_size = dimensions.row_byte_size();
+        let location = location.clone();
+        let commitments = commitments.map(|x| x.to_vec());
+        let rows = rows.to_vec();
+        let params = self.params.clone();
+        self.compute
+            .run(move || {
+                rows.par_iter()
+                    .filter_map(|row| {
+                        let Some(segment) =
+                            segments.get(&(location.segment_indexes[*row] as usize))
+                        else {
+                            debug!("row {:?} unavailable", row);
+                            return None;
+                        };
+                        let (row_bytes, served) = match split_row(
+                            segment,
+                            location.offsets[*row] as usize,
+                            row_byte_size,
+                        ) {
+                            Ok(x) => x,
+                            Err(e) => {
+                                debug!("row {:?} malformed: {:?}", row, e.to_string());
+                                return None;
+                            }
+                        };
+                        let expected = commitments.as_ref().map_or(served, |x| &x[*row]);
+                        match row_commitment(&params.srs, cols, row_bytes) {
+                            Ok(x) if x == *expected => Some((*row, row_bytes.to_vec())),
+                            _ => {
+                                debug!("row {:?} does not match its commitment", row);
+                                None
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .await
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, bail, Result};
+use common::{error::SampleError, COEFF_DATA_SIZE, COEFF_SIZE, COMMITMENT_SIZE};
+use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
+use kate::{
+    gridgen::{AsBytes, EvaluationGrid, PolynomialGrid},
+    M1NoPrecomp,
+};
+use kate_recovery::{
+    data::Cell,
+    matrix::{Dimensions, Position},
+    proof,
+};
+
+/// Splits the row starting at `offset` of a segment into its coefficients and commitment
+pub fn split_row(
+    segment: &[u8],
+    offset: usize,
+    row_byte_size: usize,
+) -> Result<(&[u8], &[u8; COMMITMENT_SIZE as usize])> {
+    let end = offset + row_byte_size + COMMITMENT_SIZE as usize;
+    if segment.len() < end {
+        bail!(SampleError::MalformedSegment(format!(
+            "row at offset {:?} exceeds segment of {:?} bytes",
+            offset,
+            segment.len()
+        )));
+    }
+    let (row, commitment) = segment[offset..end].split_at(row_byte_size);
+    Ok((row, commitment.try_into()?))
+}
+
+/// Recomputes the commitment of a single row from its coefficients
+pub fn row_commitment(
+    srs: &M1NoPrecomp,
+    cols: usize,
+    row: &[u8],
+) -> Result<[u8; COMMITMENT_SIZE as usize]> {
+    RowPolynomial::new(cols, row)?.commitment(srs)
+}
+
+/// Returns the blob bytes carried by a row, skipping the padding byte of each coefficient
+pub fn row_data(row: &[u8]) -> impl Iterator<Item = &u8> {
+    row.chunks(COEFF_SIZE as usize)
+        .flat_map(|coeff| &coeff[..COEFF_DATA_SIZE as usize])
+}
+
+/// A row interpolated once, so that any number of its cells can be proven
+pub struct RowPolynomial {
+    evals: EvaluationGrid,
+    polys: PolynomialGrid,
+}
+
+impl RowPolynomial {
+    pub fn new(cols: usize, row: &[u8]) -> Result<Self> {
+        // generate 1-row matrix
+        let evals = EvaluationGrid::from_row_slices(1, cols, row.to_vec())
+            .map_err(|e| anyhow!(format!("Grid construction failed: {:?}", e)))?;
+        // make polynomial
+        let polys = evals
+            .make_polynomial_grid()
+            .map_err(|e| anyhow!(format!("Make polynomial grid failed: {:?}", e)))?;
+        Ok(Self { evals, polys })
+    }
+
+    pub fn commitment(&self, srs: &M1NoPrecomp) -> Result<[u8; COMMITMENT_SIZE as usize]> {
+        let commitment = self
+            .polys
+            .commitment(srs, 0)
+            .map_err(|e| anyhow!(format!("Make commitment failed: {:?}", e)))?;
+        Ok(commitment.to_bytes().expect("Ser cannot fail"))
+    }
+
+    /// Builds the cell in column `col` together with its proof, as row 0 of a 1-row matrix
+    pub fn cell(&self, srs: &M1NoPrecomp, col: u16) -> Result<Cell> {
+        let Some(data) = self.evals.get::<usize, usize>(0, col as usize) else {
+            bail!(anyhow!(
+                "Invalid column {:?} for dims {:?}",
+                col,
+                self.evals.dims()
+            ));
+        };
+        let proof = match self.polys.proof(
+            srs,
+            &kate::com::Cell {
+                row: zerog_core::BlockLengthRows(0),
+                col: zerog_core::BlockLengthColumns(col.into()),
+            },
+        ) {
+            Ok(x) => x,
+            Err(e) => bail!(anyhow!("Unable to make proof: {:?}", e)),
+        };
+
+        let data = data.to_bytes().expect("Ser cannot fail").to_vec();
+        let proof = proof.to_bytes().expect("Ser cannot fail").to_vec();
+        let content = [proof, data].into_iter().flatten().collect::<Vec<_>>();
+        Ok(Cell {
+            position: Position { row: 0, col },
+            content: content.as_slice().try_into()?,
+        })
+    }
+
+    /// Proves the cell in column `col` and verifies the proof against the row commitment
+    pub fn verify_cell(
+        &self,
+        pp: &PublicParameters,
+        srs: &M1NoPrecomp,
+        dimensions: Dimensions,
+        commitment: &[u8; COMMITMENT_SIZE as usize],
+        col: u16,
+    ) -> Result<bool> {
+        let cell = self.cell(srs, col)?;
+        Ok(proof::verify(pp, dimensions, commitment, &cell)?)
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use common::{COEFF_SIZE, COMMITMENT_SIZE};
+use dusk_bls12_381::{BlsScalar, G1Affine, G1Projective};
+use dusk_bytes::Serializable;
+use dusk_plonk::commitment_scheme::kzg10::PublicParameters;
+use kate::M1NoPrecomp;
+use kate_recovery::matrix::Dimensions;
+use rand::rngs::OsRng;
+use rayon::prelude::*;
+
+use crate::{report::CellVerdict, row::RowPolynomial};
+
+/// A downloaded row together with the columns sampled in it
+pub struct SampledRow {
+    pub data: Vec<u8>,
+    pub commitment: [u8; COMMITMENT_SIZE as usize],
+    pub cols: Vec<u16>,
+}
+
+/// Public parameters of the commitment scheme, loading them is slow so they are loaded once
+/// and shared by every verification
+pub struct Params {
+    pub pp: PublicParameters,
+    pub srs: M1NoPrecomp,
+}
+
+impl Params {
+    pub fn load() -> Self {
+        Self {
+            pp: kate_recovery::couscous::public_params(),
+            srs: kate::couscous::multiproof_params(),
+        }
+    }
+}
+
+pub struct Verifier<'a> {
+    params: &'a Params,
+    dimensions: Dimensions,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(params: &'a Params, dimensions: Dimensions) -> Self {
+        Self { params, dimensions }
+    }
+
+    /// Verifies every sampled cell of the rows in parallel. All rows are checked at once with
+    /// [`Verifier::verify_batch`], and only if the batch fails each cell is proven and verified
+    /// on its own to find the bad ones. Returns the verdict and verify time of each cell, cells
+    /// verified in a batch share the batch time.
+    pub fn verify(&self, rows: &[SampledRow]) -> Vec<Vec<(CellVerdict, Duration)>> {
+        let timer = Instant::now();
+        match self.verify_batch(rows) {
+            Ok(true) => {
+                let elapsed = timer.elapsed();
+                debug!(
+                    "batch verification of {:?} rows used {:?}ms",
+                    rows.len(),
+                    elapsed.as_millis()
+                );
+                return rows
+                    .iter()
+                    .map(|row| vec![(CellVerdict::Verified, elapsed); row.cols.len()])
+                    .collect();
+            }
+            Ok(false) => info!("batch verification failed, verifying cells one by one"),
+            Err(e) => info!(
+                "batch verification failed with error {:?}, verifying cells one by one",
+                e.to_string()
+            ),
+        }
+        rows.par_iter().map(|row| self.verify_cells(row)).collect()
+    }
+
+    /// Checks all rows against their commitments with a single commitment computation. For
+    /// random scalars `r_i`, the row `sum(r_i * row_i)` must commit to
+    /// `sum(r_i * commitment_i)`, which holds for all `r_i` only if every row matches its
+    /// commitment. A row that matches its commitment has valid proofs for all of its cells.
+    pub fn verify_batch(&self, rows: &[SampledRow]) -> Result<bool> {
+        if rows.is_empty() {
+            return Ok(true);
+        }
+        let cols = u16::from(self.dimensions.cols()) as usize;
+        let (combined_row, combined_commitment) = rows
+            .par_iter()
+            .map(|row| -> Result<(Vec<BlsScalar>, G1Projective)> {
+                let r = BlsScalar::random(&mut OsRng);
+                let row_part = row
+                    .data
+                    .chunks(COEFF_SIZE as usize)
+                    .map(|coeff| -> Result<BlsScalar> {
+                        let coeff = BlsScalar::from_bytes(coeff.try_into()?)
+                            .map_err(|e| anyhow!(format!("Invalid coefficient: {:?}", e)))?;
+                        Ok(coeff * r)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let commitment = G1Affine::from_bytes(&row.commitment)
+                    .map_err(|e| anyhow!(format!("Invalid commitment: {:?}", e)))?;
+                Ok((row_part, commitment * r))
+            })
+            .try_reduce(
+                || (vec![BlsScalar::zero(); cols], G1Projective::identity()),
+                |(row_a, commitment_a), (row_b, commitment_b)| {
+                    let row = row_a.iter().zip(row_b.iter()).map(|(a, b)| a + b).collect();
+                    Ok((row, commitment_a + commitment_b))
+                },
+            )?;
+        let combined_row = combined_row
+            .iter()
+            .flat_map(|x| x.to_bytes())
+            .collect::<Vec<_>>();
+        Ok(
+            RowPolynomial::new(cols, &combined_row)?.commitment(&self.params.srs)?
+                == G1Affine::from(combined_commitment).to_bytes(),
+        )
+    }
+
+    /// Proves and verifies each sampled cell of a row on its own
+    pub fn verify_cells(&self, row: &SampledRow) -> Vec<(CellVerdict, Duration)> {
+        let cols = u16::from(self.dimensions.cols()) as usize;
+        let polynomial = match RowPolynomial::new(cols, &row.data) {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("row malformed: {:?}", e.to_string());
+                return vec![(CellVerdict::Malformed, Duration::ZERO); row.cols.len()];
+            }
+        };
+        row.cols
+            .par_iter()
+            .map(|col| {
+                let timer = Instant::now();
+                let verdict = match polynomial.verify_cell(
+                    &self.params.pp,
+                    &self.params.srs,
+                    self.dimensions,
+                    &row.commitment,
+                    *col,
+                ) {
+                    Ok(true) => CellVerdict::Verified,
+                    Ok(false) => CellVerdict::InvalidProof,
+                    Err(e) => {
+                        debug!("cell at column {:?} malformed: {:?}", col, e.to_string());
+                        CellVerdict::Malformed
+                    }
+                };
+                (verdict, timer.elapsed())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::row::row_commitment;
+
+    use super::*;
+
+    const ROWS: u16 = 4;
+    const COLS: u16 = 8;
+
+    fn coefficient(x: u64) -> [u8; COEFF_SIZE as usize] {
+        BlsScalar::from(x).to_bytes()
+    }
+
+    fn honest_rows(params: &Params) -> Vec<SampledRow> {
+        (0..ROWS as u64)
+            .map(|row| {
+                let data = (0..COLS as u64)
+                    .flat_map(|col| coefficient(row * 100 + col))
+                    .collect::<Vec<_>>();
+                SampledRow {
+                    commitment: row_commitment(&params.srs, COLS as usize, &data).unwrap(),
+                    data,
+                    cols: vec![1, 5],
+                }
+            })
+            .collect()
+    }
+
+    // replaces the coefficient in column 3 with another valid scalar
+    fn tamper(row: &mut SampledRow) {
+        let offset = 3 * COEFF_SIZE as usize;
+        row.data[offset..offset + COEFF_SIZE as usize].copy_from_slice(&coefficient(7));
+    }
+
+    fn verifier(params: &Params) -> Verifier<'_> {
+        Verifier::new(params, Dimensions::new(ROWS, COLS).unwrap())
+    }
+
+    #[test]
+    fn honest_rows_pass_the_batch_check() {
+        let params = Params::load();
+        let rows = honest_rows(&params);
+        assert!(verifier(&params).verify_batch(&rows).unwrap());
+        assert!(verifier(&params).verify_batch(&[]).unwrap());
+    }
+
+    #[test]
+    fn tampered_rows_fail_the_batch_check() {
+        let params = Params::load();
+
+        let mut rows = honest_rows(&params);
+        tamper(&mut rows[2]);
+        assert!(!verifier(&params).verify_batch(&rows).unwrap());
+
+        let mut rows = honest_rows(&params);
+        rows[1].commitment = rows[0].commitment;
+        assert!(!verifier(&params).verify_batch(&rows).unwrap());
+    }
+
+    #[test]
+    fn only_cells_of_the_bad_row_are_invalid() {
+        let params = Params::load();
+        let mut rows = honest_rows(&params);
+        rows[2].cols = vec![5];
+        tamper(&mut rows[2]);
+
+        let verdicts = verifier(&params)
+            .verify(&rows)
+            .into_iter()
+            .map(|row| row.into_iter().map(|(x, _)| x).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            [
+                vec![CellVerdict::Verified; 2],
+                vec![CellVerdict::Verified; 2],
+                vec![CellVerdict::InvalidProof],
+                vec![CellVerdict::Verified; 2],
+            ]
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Result};
+use data_fetcher::kv_fetcher::fetch_kv_keys;
+use ethereum_types::H256;
+
+use crate::{
+    record::{not_finalized, SampleRun},
+    SamplePositions, Sampler, DEFAULT_WITHHOLDING_RATIO,
+};
+
+// keys of a stream fetched by one request, a poll walks all keys page by page
+const KEYS_PER_PAGE: usize = 1024;
+// how long a batch whose file is not finalized is retried before it is recorded as failed
+const MAX_PENDING_AGE: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Debug)]
+pub struct WatcherConfig {
+    pub stream_ids: Vec<H256>,
+    /// confidence every blob is sampled at
+    pub confidence: f64,
+    pub poll_interval: Duration,
+}
+
+/// Progress of the watcher through the keys of a stream. Keys are listed in key order, not
+/// in version order, so new keys are found by walking all keys again on every poll. A walk
+/// only samples keys written after the walk before the previous one ended: those keys are
+/// certain not to have been seen by a complete walk yet.
+#[derive(Default)]
+struct StreamProgress {
+    // key the walk continues after, `None` to start a new walk
+    cursor: Option<Vec<u8>>,
+    // keys at or below this version were handled, `None` during the first walk, which only
+    // finds the head of the stream so that history is not sampled
+    threshold: Option<u64>,
+    // highest version seen so far
+    walk_version: u64,
+    // highest version seen when the previous walk ended
+    last_walk_version: u64,
+    // keys above the threshold that were sampled, with their versions
+    sampled: HashMap<Vec<u8>, u64>,
+    // keys whose file was not finalized yet, sampled again on every poll
+    pending: Vec<PendingKey>,
+}
+
+/// Key of a batch whose file was not finalized when it was sampled
+struct PendingKey {
+    key: Vec<u8>,
+    version: u64,
+    // when the batch was first found not finalized
+    since: SystemTime,
+}
+
+impl StreamProgress {
+    /// Whether the key of the given version is new and was not sampled yet
+    fn is_new(&self, key: &[u8], version: u64) -> bool {
+        self.threshold.is_some_and(|x| version > x)
+            && !self.sampled.contains_key(key)
+            && !self.pending.iter().any(|x| x.key == key)
+    }
+
+    /// Starts the next walk, keys written while the walk that just ended ran may be behind
+    /// its cursor, so the threshold only moves up to the head seen by the walk before it
+    fn end_walk(&mut self) {
+        let threshold = match self.threshold {
+            None => self.walk_version,
+            Some(_) => self.last_walk_version,
+        };
+        self.sampled.retain(|_, version| *version > threshold);
+        self.threshold = Some(threshold);
+        self.last_walk_version = self.walk_version;
+        self.cursor = None;
+    }
+}
+
+/// Polls the kv streams for new batch headers and samples every blob of each new batch, the
+/// results are kept by the recorder of the sampler
+pub struct Watcher {
+    sampler: Arc<Sampler>,
+    config: WatcherConfig,
+    streams: HashMap<H256, StreamProgress>,
+}
+
+impl Watcher {
+    pub fn new(sampler: Arc<Sampler>, config: WatcherConfig) -> Self {
+        Self {
+            sampler,
+            config,
+            streams: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        info!(
+            "watching streams {:?} every {:?}s",
+            self.config.stream_ids,
+            self.config.poll_interval.as_secs()
+        );
+        let mut interval = tokio::time::interval(self.config.poll_interval);
+        loop {
+            interval.tick().await;
+            for stream_id in self.config.stream_ids.clone() {
+                if let Err(e) = self.poll(stream_id).await {
+                    warn!("poll stream {:?} failed: {:?}", stream_id, e.to_string());
+                }
+            }
+        }
+    }
+
+    async fn poll(&mut self, stream_id: H256) -> Result<()> {
+        let mut progress = self.streams.remove(&stream_id).unwrap_or_default();
+        let result = self.poll_stream(stream_id, &mut progress).await;
+        self.streams.insert(stream_id, progress);
+        result
+    }
+
+    async fn poll_stream(&self, stream_id: H256, progress: &mut StreamProgress) -> Result<()> {
+        for pending in std::mem::take(&mut progress.pending) {
+            self.sample(
+                stream_id,
+                pending.key,
+                pending.version,
+                Some(pending.since),
+                progress,
+            )
+            .await;
+        }
+
+        // the walk runs to its end, so a new key is found within two polls however long the
+        // stream is, a failed page is fetched again by the next poll
+        loop {
+            let keys = fetch_kv_keys(
+                self.sampler.kv_client.clone(),
+                stream_id,
+                progress.cursor.clone(),
+                KEYS_PER_PAGE,
+            )
+            .await?;
+            let walk_ended = keys.len() < KEYS_PER_PAGE;
+            if let Some((key, _)) = keys.last() {
+                progress.cursor = Some(key.clone());
+            }
+
+            let mut new_keys = vec![];
+            for (key, version) in keys {
+                progress.walk_version = progress.walk_version.max(version);
+                if progress.is_new(&key, version) {
+                    new_keys.push((key, version));
+                }
+            }
+            new_keys.sort_by_key(|(_, version)| *version);
+            for (batch_header_hash, version) in new_keys {
+                self.sample(stream_id, batch_header_hash, version, None, progress)
+                    .await;
+            }
+
+            if walk_ended {
+                progress.end_walk();
+                debug!(
+                    "walked stream {:?}, sampling keys above version {:?}",
+                    stream_id, progress.threshold
+                );
+                return Ok(());
+            }
+        }
+    }
+
+    /// Samples every blob of the batch, a batch whose file is not finalized is kept pending
+    /// for up to [`MAX_PENDING_AGE`] since `pending_since`
+    async fn sample(
+        &self,
+        stream_id: H256,
+        batch_header_hash: Vec<u8>,
+        version: u64,
+        pending_since: Option<SystemTime>,
+        progress: &mut StreamProgress,
+    ) {
+        let result = self
+            .sampler
+            .sample_batch(
+                stream_id,
+                batch_header_hash.clone(),
+                SamplePositions::Confidence {
+                    confidence: self.config.confidence,
+                    withholding_ratio: DEFAULT_WITHHOLDING_RATIO,
+                    seed: None,
+                },
+            )
+            .await;
+        match result {
+            Err(e) if not_finalized(&e) => {
+                let since = pending_since.unwrap_or_else(SystemTime::now);
+                let pending_for = since.elapsed().unwrap_or_default();
+                if pending_for < MAX_PENDING_AGE {
+                    // the batch is sampled again from the next poll on
+                    info!(
+                        "batch {:x?} of stream {:?} not finalized yet",
+                        batch_header_hash, stream_id
+                    );
+                    progress.pending.push(PendingKey {
+                        key: batch_header_hash,
+                        version,
+                        since,
+                    });
+                    return;
+                }
+                warn!(
+                    "batch {:x?} of stream {:?} not finalized after {:?}s, giving up",
+                    batch_header_hash,
+                    stream_id,
+                    pending_for.as_secs()
+                );
+                self.sampler.record(SampleRun {
+                    stream_id,
+                    batch_header_hash: &batch_header_hash,
+                    blob_index: None,
+                    started_at: since,
+                    elapsed: pending_for,
+                    result: &Err(anyhow!(
+                        "{}, pending for {:?}s",
+                        e.to_string(),
+                        pending_for.as_secs()
+                    )),
+                });
+            }
+            Ok(blobs) if blobs.iter().all(|x| x.as_ref().is_ok_and(|x| x.success())) => {
+                info!(
+                    "batch {:x?} of stream {:?} available, {:?} blobs",
+                    batch_header_hash,
+                    stream_id,
+                    blobs.len()
+                )
+            }
+            Ok(blobs) => warn!(
+                "batch {:x?} of stream {:?} failed sampling, blobs: {:?}",
+                batch_header_hash,
+                stream_id,
+                blobs
+                    .iter()
+                    .map(|x| x.as_ref().is_ok_and(|x| x.success()))
+                    .collect::<Vec<_>>()
+            ),
+            Err(e) => warn!(
+                "batch {:x?} of stream {:?} failed sampling: {:?}",
+                batch_header_hash,
+                stream_id,
+                e.to_string()
+            ),
+        }
+        progress.sampled.insert(batch_header_hash, version);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn walk(progress: &mut StreamProgress, keys: &[(u8, u64)]) -> Vec<u8> {
+        let mut new_keys = vec![];
+        for (key, version) in keys {
+            progress.walk_version = progress.walk_version.max(*version);
+            if progress.is_new(&[*key], *version) {
+                new_keys.push(*key);
+                progress.sampled.insert(vec![*key], *version);
+            }
+        }
+        progress.end_walk();
+        new_keys
+    }
+
+    #[test]
+    fn first_walk_starts_from_head() {
+        let mut progress = StreamProgress::default();
+        assert!(walk(&mut progress, &[(1, 5), (2, 3)]).is_empty());
+        assert_eq!(walk(&mut progress, &[(1, 5), (2, 3), (3, 6)]), [3]);
+        assert!(walk(&mut progress, &[(1, 5), (2, 3), (3, 6)]).is_empty());
+    }
+
+    #[test]
+    fn keys_written_behind_the_cursor_are_found() {
+        let mut progress = StreamProgress::default();
+        walk(&mut progress, &[(5, 1)]);
+        // key 1 was written at version 2 after the walk passed it, key 9 at version 3
+        assert_eq!(walk(&mut progress, &[(5, 1), (9, 3)]), [9]);
+        assert_eq!(walk(&mut progress, &[(1, 2), (5, 1), (9, 3)]), [1]);
+        assert!(walk(&mut progress, &[(1, 2), (5, 1), (9, 3)]).is_empty());
+    }
+
+    #[test]
+    fn keys_of_the_same_version_are_not_skipped() {
+        let mut progress = StreamProgress::default();
+        walk(&mut progress, &[(1, 1)]);
+        // key 2 waits for its file to be finalized, key 3 of the same version is sampled
+        progress.pending.push(PendingKey {
+            key: vec![2],
+            version: 2,
+            since: SystemTime::now(),
+        });
+        assert_eq!(walk(&mut progress, &[(1, 1), (2, 2), (3, 2)]), [3]);
+        // a pending key dropped before it was sampled is found again
+        progress.pending.clear();
+        assert_eq!(walk(&mut progress, &[(1, 1), (2, 2), (3, 2)]), [2]);
+    }
+}
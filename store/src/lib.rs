@@ -0,0 +1,361 @@
+#[macro_use]
+extern crate tracing;
+
+use std::{
+    path::Path,
+    sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use ethereum_types::H256;
+use sampler::{CellVerdict, SampleRecorder, SampleRun};
+use serde::{Deserialize, Serialize};
+use sled::{Db, Tree};
+
+const SAMPLES_TREE: &str = "samples";
+const BATCH_INDEX_TREE: &str = "batch_index";
+// how often records older than the retention are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// records waiting for the writer, runs recorded while it is full are dropped
+const WRITE_QUEUE_RECORDS: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Verdict {
+    Verified,
+    InvalidProof,
+    Unavailable,
+    Malformed,
+    Pending,
+}
+
+impl From<CellVerdict> for Verdict {
+    fn from(verdict: CellVerdict) -> Self {
+        match verdict {
+            CellVerdict::Verified => Self::Verified,
+            CellVerdict::InvalidProof => Self::InvalidProof,
+            CellVerdict::Unavailable => Self::Unavailable,
+            CellVerdict::Malformed => Self::Malformed,
+            CellVerdict::Pending => Self::Pending,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CellRecord {
+    pub row: u32,
+    pub col: u16,
+    pub segment_index: u32,
+    pub node: Option<String>,
+    #[serde(default)]
+    pub cached: bool,
+    pub download_time_us: u64,
+    pub verify_time_us: u64,
+    pub verdict: Verdict,
+}
+
+/// A stored sampling run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SampleRecord {
+    pub stream_id: H256,
+    pub batch_header_hash: Vec<u8>,
+    /// `None` if the whole batch could not be sampled
+    pub blob_index: Option<u32>,
+    /// unix time the run started at, in milliseconds
+    pub timestamp_ms: u64,
+    pub elapsed_us: u64,
+    pub success: bool,
+    pub confidence: f64,
+    /// cells were verified against row commitments anchored by the batch root, false for
+    /// records stored before this was tracked
+    #[serde(default)]
+    pub anchored: bool,
+    pub cells: Vec<CellRecord>,
+    pub error: Option<String>,
+}
+
+impl From<SampleRun<'_>> for SampleRecord {
+    fn from(run: SampleRun) -> Self {
+        let timestamp_ms = run
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut record = Self {
+            stream_id: run.stream_id,
+            batch_header_hash: run.batch_header_hash.to_vec(),
+            blob_index: run.blob_index,
+            timestamp_ms,
+            elapsed_us: run.elapsed.as_micros() as u64,
+            success: false,
+            confidence: 0.0,
+            anchored: false,
+            cells: vec![],
+            error: None,
+        };
+        match run.result {
+            Ok(report) => {
+                record.success = report.success();
+                record.confidence = report.confidence;
+                record.anchored = report.anchored;
+                record.cells = report
+                    .cells
+                    .iter()
+                    .map(|cell| CellRecord {
+                        row: cell.position.row,
+                        col: cell.position.col,
+                        segment_index: cell.segment_index,
+                        node: cell.node.clone(),
+                        cached: cell.cached,
+                        download_time_us: cell.download_time.as_micros() as u64,
+                        verify_time_us: cell.verify_time.as_micros() as u64,
+                        verdict: cell.verdict.into(),
+                    })
+                    .collect();
+            }
+            Err(e) => record.error = Some(e.to_string()),
+        }
+        record
+    }
+}
+
+/// On-disk history of sampling runs. Records are keyed by timestamp and a unique id, and
+/// indexed by stream and batch header hash. Recorded runs are written by a thread of the
+/// store, so recording never blocks the sampling task, runs recorded faster than the disk
+/// keeps up with are dropped.
+pub struct SampleStore {
+    trees: Trees,
+    writer: SyncSender<SampleRecord>,
+}
+
+impl SampleStore {
+    /// Opens the store at `path`, records older than `retention` are deleted, all records are
+    /// kept if it is `None`
+    pub fn open(path: impl AsRef<Path>, retention: Option<Duration>) -> Result<Self> {
+        Self::with_db(sled::open(path)?, retention)
+    }
+
+    fn with_db(db: Db, retention: Option<Duration>) -> Result<Self> {
+        let trees = Trees {
+            samples: db.open_tree(SAMPLES_TREE)?,
+            batch_index: db.open_tree(BATCH_INDEX_TREE)?,
+            db,
+        };
+        let (writer, records) = sync_channel(WRITE_QUEUE_RECORDS);
+        let writer_trees = trees.clone();
+        std::thread::Builder::new()
+            .name("sample-store".to_string())
+            .spawn(move || write(writer_trees, records, retention))?;
+        Ok(Self { trees, writer })
+    }
+
+    /// Records of a batch, oldest first
+    pub fn by_batch(
+        &self,
+        stream_id: H256,
+        batch_header_hash: &[u8],
+        limit: usize,
+    ) -> Result<Vec<SampleRecord>> {
+        let trees = &self.trees;
+        trees
+            .batch_index
+            .scan_prefix(batch_prefix(stream_id, batch_header_hash))
+            .values()
+            .take(limit)
+            .filter_map(|key| match key {
+                Ok(key) => trees.samples.get(key).transpose(),
+                Err(e) => Some(Err(e)),
+            })
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+
+    /// Records of runs started in `[start_ms, end_ms)`, oldest first
+    pub fn by_time(&self, start_ms: u64, end_ms: u64, limit: usize) -> Result<Vec<SampleRecord>> {
+        self.trees
+            .samples
+            .range(start_ms.to_be_bytes()..end_ms.to_be_bytes())
+            .values()
+            .take(limit)
+            .map(|value| Ok(serde_json::from_slice(&value?)?))
+            .collect()
+    }
+}
+
+impl SampleRecorder for SampleStore {
+    fn record(&self, run: SampleRun) {
+        match self.writer.try_send(SampleRecord::from(run)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(record)) => error!(
+                "store sample record failed: writer queue full, dropped run of batch {:x?}",
+                record.batch_header_hash
+            ),
+            Err(TrySendError::Disconnected(_)) => {
+                error!("store sample record failed: writer stopped")
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Trees {
+    db: Db,
+    samples: Tree,
+    batch_index: Tree,
+}
+
+impl Trees {
+    fn insert(&self, record: &SampleRecord) -> Result<()> {
+        let mut key = record.timestamp_ms.to_be_bytes().to_vec();
+        key.extend(self.db.generate_id()?.to_be_bytes());
+        let mut index_key = batch_prefix(record.stream_id, &record.batch_header_hash);
+        index_key.extend(&key);
+        self.samples.insert(&key, serde_json::to_vec(record)?)?;
+        self.batch_index.insert(index_key, key)?;
+        Ok(())
+    }
+
+    /// Deletes the records of runs started before `before_ms`, returns how many were deleted
+    fn prune(&self, before_ms: u64) -> Result<usize> {
+        let mut pruned = 0;
+        for entry in self.samples.range(..before_ms.to_be_bytes()) {
+            let (key, value) = entry?;
+            let record: SampleRecord = serde_json::from_slice(&value)?;
+            let mut index_key = batch_prefix(record.stream_id, &record.batch_header_hash);
+            index_key.extend(key.iter());
+            self.batch_index.remove(index_key)?;
+            self.samples.remove(key)?;
+            pruned += 1;
+        }
+        Ok(pruned)
+    }
+}
+
+/// Writes the recorded runs until the store is dropped, deleting expired records on the way
+fn write(trees: Trees, records: Receiver<SampleRecord>, retention: Option<Duration>) {
+    let mut pruned_at: Option<Instant> = None;
+    loop {
+        match records.recv_timeout(PRUNE_INTERVAL) {
+            Ok(record) => {
+                if let Err(e) = trees.insert(&record) {
+                    error!("store sample record failed: {:?}", e.to_string());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let Some(retention) = retention else {
+            continue;
+        };
+        if pruned_at.is_some_and(|at| at.elapsed() < PRUNE_INTERVAL) {
+            continue;
+        }
+        pruned_at = Some(Instant::now());
+        let before_ms = SystemTime::now()
+            .checked_sub(retention)
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default()
+            .as_millis() as u64;
+        match trees.prune(before_ms) {
+            Ok(0) => {}
+            Ok(pruned) => info!("deleted {:?} expired sample records", pruned),
+            Err(e) => error!("delete expired sample records failed: {:?}", e.to_string()),
+        }
+    }
+}
+
+// batch header hashes are prefixed with their length so that one never prefixes another
+fn batch_prefix(stream_id: H256, batch_header_hash: &[u8]) -> Vec<u8> {
+    let mut prefix = stream_id.as_bytes().to_vec();
+    prefix.extend((batch_header_hash.len() as u32).to_be_bytes());
+    prefix.extend(batch_header_hash);
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> SampleStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        SampleStore::with_db(db, None).unwrap()
+    }
+
+    fn record(stream: u8, batch_header_hash: &[u8], timestamp_ms: u64) -> SampleRecord {
+        SampleRecord {
+            stream_id: H256::repeat_byte(stream),
+            batch_header_hash: batch_header_hash.to_vec(),
+            blob_index: Some(0),
+            timestamp_ms,
+            elapsed_us: 0,
+            success: true,
+            confidence: 0.0,
+            anchored: true,
+            cells: vec![],
+            error: None,
+        }
+    }
+
+    fn timestamps(records: Vec<SampleRecord>) -> Vec<u64> {
+        records.iter().map(|x| x.timestamp_ms).collect()
+    }
+
+    #[test]
+    fn by_batch_is_isolated_and_limited() {
+        let store = store();
+        for record in [
+            record(1, &[1], 30),
+            record(1, &[1], 10),
+            record(1, &[1], 20),
+            // a hash the other one is a prefix of, and the same hash in another stream
+            record(1, &[1, 2], 15),
+            record(2, &[1], 25),
+        ] {
+            store.trees.insert(&record).unwrap();
+        }
+
+        let stream = H256::repeat_byte(1);
+        assert_eq!(
+            timestamps(store.by_batch(stream, &[1], 10).unwrap()),
+            [10, 20, 30]
+        );
+        assert_eq!(
+            timestamps(store.by_batch(stream, &[1], 2).unwrap()),
+            [10, 20]
+        );
+        assert_eq!(
+            timestamps(store.by_batch(stream, &[1, 2], 10).unwrap()),
+            [15]
+        );
+        assert!(store.by_batch(stream, &[2], 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn by_time_includes_start_and_excludes_end() {
+        let store = store();
+        for timestamp_ms in [10, 20, 30] {
+            store.trees.insert(&record(1, &[1], timestamp_ms)).unwrap();
+        }
+
+        assert_eq!(timestamps(store.by_time(10, 30, 10).unwrap()), [10, 20]);
+        assert_eq!(timestamps(store.by_time(11, 31, 10).unwrap()), [20, 30]);
+        assert_eq!(timestamps(store.by_time(0, 100, 1).unwrap()), [10]);
+    }
+
+    #[test]
+    fn prune_removes_records_and_their_index_entries() {
+        let store = store();
+        store.trees.insert(&record(1, &[1], 10)).unwrap();
+        store.trees.insert(&record(1, &[1], 20)).unwrap();
+
+        assert_eq!(store.trees.prune(15).unwrap(), 1);
+        assert_eq!(store.trees.samples.len(), 1);
+        assert_eq!(store.trees.batch_index.len(), 1);
+        assert_eq!(
+            timestamps(store.by_batch(H256::repeat_byte(1), &[1], 10).unwrap()),
+            [20]
+        );
+        assert_eq!(store.trees.prune(15).unwrap(), 0);
+    }
+}